@@ -0,0 +1,253 @@
+use crate::VARIANCE;
+use std::fmt;
+use std::fs::File;
+use std::io::prelude::*;
+use std::str::FromStr;
+
+/// Furthest either switching point can move.
+const MAX_OFFSET: f32 = 1.5;
+/// The switching points are kept at least this far apart, so the relay doesn't chatter.
+const MIN_BAND: f32 = 0.4;
+/// Fraction of each miss that's corrected, so one odd cycle doesn't throw it off.
+const LEARNING_RATE: f32 = 0.5;
+
+/// Learns how far the room keeps going after the relay switches, and switches early to make up
+/// for it.
+///
+/// Radiators keep heating the room for a while after the relay goes off, so the temperature
+/// carries on past the top of the band, and take a while to warm up so it drops below the bottom
+/// before heat arrives. After each off period the peak is compared with the top of the band and
+/// the cut-off point moved by part of the difference, and after each on period the same is done
+/// with the trough and the cut-in point.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Anticipator {
+    /// How far below the top of the band the relay switches off
+    off_offset: f32,
+    /// How far above the bottom of the band the relay switches on
+    on_offset: f32,
+    /// What's being watched since the relay last switched
+    phase: Option<Phase>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Phase {
+    running: bool,
+    target: f32,
+    /// Lowest temperature while running, highest while not
+    extreme: f32,
+    /// Watched since the relay switched, rather than from part way through
+    whole: bool,
+}
+
+impl Anticipator {
+    /// Temperature above which the relay switches off.
+    pub(crate) fn cut_off(&self, target: f32) -> f32 {
+        target + VARIANCE - self.off_offset
+    }
+
+    /// Temperature below which the relay switches on.
+    pub(crate) fn cut_in(&self, target: f32) -> f32 {
+        target - VARIANCE + self.on_offset
+    }
+
+    /// Watch a reading, returning whether the offsets changed.
+    ///
+    /// A phase is only learned from if it was watched from the switch, with the same target
+    /// throughout.
+    pub(crate) fn observe(&mut self, temperature: f32, target: f32, running: bool) -> bool {
+        let phase = match self.phase.as_mut() {
+            Some(phase) if phase.target == target && phase.running == running => {
+                phase.extreme = if running {
+                    phase.extreme.min(temperature)
+                } else {
+                    phase.extreme.max(temperature)
+                };
+                return false;
+            }
+            phase => phase.copied(),
+        };
+
+        let switched = matches!(phase, Some(phase) if phase.target == target);
+        self.phase = Some(Phase {
+            running,
+            target,
+            extreme: temperature,
+            whole: switched,
+        });
+
+        match phase {
+            Some(phase) if switched && phase.whole => {
+                self.learn(phase);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Stop watching, e.g. while the relay is driven some other way.
+    pub(crate) fn reset(&mut self) {
+        self.phase = None;
+    }
+
+    fn learn(&mut self, phase: Phase) {
+        if phase.running {
+            let undershoot = (phase.target - VARIANCE) - phase.extreme;
+            self.on_offset = bounded(self.on_offset + LEARNING_RATE * undershoot, self.off_offset);
+        } else {
+            let overshoot = phase.extreme - (phase.target + VARIANCE);
+            self.off_offset = bounded(self.off_offset + LEARNING_RATE * overshoot, self.on_offset);
+        }
+    }
+
+    pub(crate) fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut file = File::open(path)?;
+        let mut contents = String::new();
+
+        file.read_to_string(&mut contents)?;
+
+        Ok(contents.parse()?)
+    }
+
+    pub(crate) fn save(&self, path: &str) -> Result<(), std::io::Error> {
+        let mut file = File::create(path)?;
+        file.write_all(self.to_string().as_bytes())
+    }
+}
+
+/// Keep an offset in range, given the one for the other end of the band.
+fn bounded(offset: f32, other: f32) -> f32 {
+    let max = MAX_OFFSET.min(2.0 * VARIANCE - MIN_BAND - other);
+    offset.min(max).max(0.0)
+}
+
+/// One `key value` pair per line, missing keys start from nothing.
+impl FromStr for Anticipator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut anticipator = Anticipator::default();
+
+        for line in s.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let mut parts = line.split_whitespace();
+            let key = parts.next().unwrap_or_default();
+            let value: f32 = parts
+                .next()
+                .and_then(|value| value.parse().ok())
+                .ok_or_else(|| format!("invalid value in anticipator line {:?}", line))?;
+
+            match key {
+                "off_offset" => anticipator.off_offset = value,
+                "on_offset" => anticipator.on_offset = value,
+                _ => return Err(format!("unknown anticipator setting {:?}", key)),
+            }
+        }
+
+        // Anything out of range was written by hand or by an older version, so start over rather
+        // than trust it
+        let Anticipator {
+            off_offset,
+            on_offset,
+            ..
+        } = anticipator;
+        if bounded(off_offset, on_offset) != off_offset
+            || bounded(on_offset, off_offset) != on_offset
+        {
+            return Err("anticipator offsets out of range".to_string());
+        }
+
+        Ok(anticipator)
+    }
+}
+
+impl fmt::Display for Anticipator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "off_offset {}", self.off_offset)?;
+        writeln!(f, "on_offset {}", self.on_offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::room::Room;
+
+    /// Run the room on hysteresis control for a day, returning the highest and lowest
+    /// temperatures in the last few hours.
+    fn run_day(room: &mut Room, anticipator: &mut Anticipator, learn: bool) -> (f32, f32) {
+        let target = 70.0;
+        let mut running = false;
+        let (mut highest, mut lowest) = (f32::MIN, f32::MAX);
+
+        for step in 0..(24 * 120) {
+            let temperature = room.reading();
+            if running && temperature > anticipator.cut_off(target) {
+                running = false;
+            } else if !running && temperature < anticipator.cut_in(target) {
+                running = true;
+            }
+            if learn {
+                anticipator.observe(temperature, target, running);
+            }
+            room.advance(running);
+
+            if step > 20 * 120 {
+                highest = highest.max(room.temperature);
+                lowest = lowest.min(room.temperature);
+            }
+        }
+
+        (highest, lowest)
+    }
+
+    #[test]
+    fn learns_to_stay_in_the_band() {
+        let mut room = Room::bedroom();
+
+        // The radiators carry it well past both ends of the band
+        let (highest, lowest) = run_day(&mut room, &mut Anticipator::default(), false);
+        assert!(highest > 71.5, "{}", highest);
+        assert!(highest - lowest > 2.5, "{} to {}", lowest, highest);
+
+        let mut anticipator = Anticipator::default();
+        run_day(&mut room, &mut anticipator, true);
+        let (highest, lowest) = run_day(&mut room, &mut anticipator, true);
+        assert!(highest < 71.3, "{} with {:?}", highest, anticipator);
+        assert!(lowest > 68.7, "{} with {:?}", lowest, anticipator);
+        assert!(anticipator.cut_off(70.0) - anticipator.cut_in(70.0) >= MIN_BAND);
+    }
+
+    #[test]
+    fn ignores_phases_with_a_target_change() {
+        let mut anticipator = Anticipator::default();
+
+        anticipator.observe(70.0, 70.0, false);
+        anticipator.observe(74.0, 70.0, false);
+        // New target part way through, so the overshoot doesn't count
+        assert!(!anticipator.observe(74.0, 72.0, false));
+        assert!(!anticipator.observe(70.0, 72.0, true));
+        assert_eq!(anticipator.off_offset, 0.0);
+
+        // Watched from the switch on, so the undershoot counts
+        assert!(anticipator.observe(72.5, 72.0, false));
+        assert_eq!(anticipator.on_offset, 0.5);
+        anticipator.observe(73.5, 72.0, false);
+        assert!(anticipator.observe(72.0, 72.0, true));
+        assert_eq!(anticipator.off_offset, 0.25);
+    }
+
+    #[test]
+    fn round_trips_through_text() {
+        let anticipator = Anticipator {
+            off_offset: 0.6,
+            on_offset: 0.25,
+            phase: None,
+        };
+
+        assert_eq!(anticipator.to_string().parse(), Ok(anticipator));
+        assert!("off_offset 3.0\n".parse::<Anticipator>().is_err());
+        assert!("off_offset 1.0\non_offset 1.0\n"
+            .parse::<Anticipator>()
+            .is_err());
+        assert!("off_offset -0.1\n".parse::<Anticipator>().is_err());
+    }
+}
@@ -0,0 +1,244 @@
+use crate::control::Gains;
+use std::f32::consts::PI;
+use std::time::{Duration, Instant};
+
+/// How far either side of the target the relay switches, in Fahrenheit.
+const HYSTERESIS: f32 = 0.3;
+/// Time constant of the filter smoothing out the sensor's steps.
+const FILTER_TIME: Duration = Duration::from_secs(60);
+/// Oscillations measured, after the first which is skewed by wherever the room started.
+const CYCLES: usize = 3;
+/// Give up rather than let the room get this far over the target.
+const MAX_OVERSHOOT: f32 = 4.0;
+/// Give up if the oscillations haven't finished by now.
+const MAX_DURATION: Duration = Duration::from_secs(8 * 60 * 60);
+
+/// What to do after a reading.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Step {
+    /// Still oscillating, with the relay on or off
+    Relay(bool),
+    Done(Tuning),
+    /// Stopped for the reason given, the gains are left alone
+    Abort(String),
+}
+
+/// Result of an experiment.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Tuning {
+    pub(crate) ultimate_gain: f32,
+    pub(crate) ultimate_period: Duration,
+    pub(crate) gains: Gains,
+}
+
+/// Relay-feedback experiment (Åström–Hägglund) for finding PID gains.
+///
+/// The relay is switched fully on below the target and fully off above it, which settles into an
+/// oscillation. Its amplitude and period give the ultimate gain and period of the room, the point
+/// where a proportional controller would oscillate on its own, and the gains follow from those.
+#[derive(Debug)]
+pub(crate) struct Autotune {
+    target: f32,
+    started: Instant,
+    /// Filtered temperature and when it was last updated
+    filtered: Option<(f32, Instant)>,
+    heating: bool,
+    /// Range of the current cycle so far, the peak and trough come after the relay switches
+    /// because of the radiators' lag
+    high: f32,
+    low: f32,
+    /// When the relay switched on each time, starting a cycle
+    cycle_starts: Vec<Instant>,
+    /// Peak to trough of each completed cycle
+    swings: Vec<f32>,
+}
+
+impl Autotune {
+    pub(crate) fn new(target: f32, now: Instant) -> Self {
+        Self {
+            target,
+            started: now,
+            filtered: None,
+            heating: false,
+            high: target,
+            low: target,
+            cycle_starts: Vec::new(),
+            swings: Vec::new(),
+        }
+    }
+
+    pub(crate) fn update(&mut self, temperature: f32, now: Instant) -> Step {
+        let filtered = match self.filtered {
+            Some((filtered, at)) => {
+                let elapsed = now.saturating_duration_since(at).as_secs_f32();
+                let weight = elapsed / (FILTER_TIME.as_secs_f32() + elapsed);
+                filtered + weight * (temperature - filtered)
+            }
+            None => temperature,
+        };
+        self.filtered = Some((filtered, now));
+
+        if filtered > self.target + MAX_OVERSHOOT {
+            return Step::Abort(format!(
+                "temperature {:.1}F is too far over the target",
+                filtered
+            ));
+        }
+        if now.saturating_duration_since(self.started) > MAX_DURATION {
+            return Step::Abort("took too long to oscillate".to_string());
+        }
+
+        self.high = self.high.max(filtered);
+        self.low = self.low.min(filtered);
+
+        if self.heating && filtered > self.target + HYSTERESIS {
+            self.heating = false;
+        } else if !self.heating && filtered < self.target - HYSTERESIS {
+            self.heating = true;
+            if !self.cycle_starts.is_empty() {
+                self.swings.push(self.high - self.low);
+            }
+            self.cycle_starts.push(now);
+            self.high = filtered;
+            self.low = filtered;
+
+            if self.cycle_starts.len() == CYCLES + 2 {
+                return self.finish();
+            }
+        }
+
+        Step::Relay(self.heating)
+    }
+
+    /// Work out the gains from the oscillations after the first.
+    fn finish(&self) -> Step {
+        let swings: f32 = self.swings[1..].iter().sum();
+        let amplitude = swings / CYCLES as f32 / 2.0;
+        if amplitude <= HYSTERESIS {
+            return Step::Abort(format!("oscillation of {:.2}F is too small", amplitude));
+        }
+
+        let first = self.cycle_starts[1];
+        let last = self.cycle_starts[CYCLES + 1];
+        let ultimate_period = (last - first) / CYCLES as u32;
+
+        // The relay swings the duty cycle half way either side of its middle
+        let relay_amplitude = 0.5;
+        let ultimate_gain =
+            4.0 * relay_amplitude / (PI * (amplitude.powi(2) - HYSTERESIS.powi(2)).sqrt());
+
+        Step::Done(Tuning {
+            ultimate_gain,
+            ultimate_period,
+            gains: tyreus_luyben(ultimate_gain, ultimate_period),
+        })
+    }
+}
+
+/// Gains from the ultimate gain and period, with time in minutes.
+///
+/// Tyreus–Luyben rather than Ziegler–Nichols, as it overshoots much less on slow loops like a
+/// room with radiators.
+fn tyreus_luyben(ultimate_gain: f32, ultimate_period: Duration) -> Gains {
+    let period = ultimate_period.as_secs_f32() / 60.0;
+    let kp = 0.45 * ultimate_gain;
+    let integral_time = 2.2 * period;
+    let derivative_time = period / 6.3;
+
+    Gains {
+        kp,
+        ki: kp / integral_time,
+        kd: kp * derivative_time,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::room::Room;
+    use crate::control::{ControlConfig, DutyCycle, Pid};
+
+    /// Run an experiment on `room`, passing readings every step.
+    fn tune(room: &mut Room, target: f32, start: Instant) -> (Step, Instant) {
+        let mut autotune = Autotune::new(target, start);
+        let mut now = start;
+
+        loop {
+            match autotune.update(room.reading(), now) {
+                Step::Relay(on) => room.advance(on),
+                step => return (step, now),
+            }
+            now += room.step();
+        }
+    }
+
+    #[test]
+    fn tunes_a_simulated_room() {
+        let mut room = Room::bedroom();
+        let start = Instant::now();
+
+        let (step, mut now) = tune(&mut room, 70.0, start);
+        let tuning = match step {
+            Step::Done(tuning) => tuning,
+            other => panic!("{:?}", other),
+        };
+        assert!(now - start < MAX_DURATION);
+        // The radiators' 10 minute lag, and the filter's, oscillate in about an hour
+        let period = tuning.ultimate_period.as_secs() / 60;
+        assert!((45..80).contains(&period), "{:?}", tuning);
+
+        // Those gains then hold the room close to the target through the same relay
+        let config = ControlConfig {
+            gains: tuning.gains,
+            ..ControlConfig::default()
+        };
+        let mut pid = Pid::default();
+        let mut cycle = DutyCycle::default();
+        let mut worst: f32 = 0.0;
+
+        for step in 0..(12 * 120) {
+            if cycle.due(&config, now) {
+                let output = pid.update(&config.gains, 70.0, room.reading(), cycle.elapsed(now));
+                cycle.start(&config, output.duty, now);
+            }
+            room.advance(cycle.relay_on(now));
+            now += room.step();
+
+            // Ignore the first few hours while it settles
+            if step > 6 * 120 {
+                worst = worst.max((room.temperature - 70.0).abs());
+            }
+        }
+        assert!(worst < 0.75, "{} off with {:?}", worst, tuning);
+    }
+
+    #[test]
+    fn aborts_when_too_warm() {
+        let start = Instant::now();
+        let mut autotune = Autotune::new(70.0, start);
+        let mut switched_off = false;
+
+        assert_eq!(autotune.update(69.0, start), Step::Relay(true));
+        for reading in 1..100 {
+            match autotune.update(80.0, start + Duration::from_secs(2 * reading)) {
+                Step::Relay(on) => switched_off |= !on,
+                Step::Abort(_) => {
+                    assert!(switched_off);
+                    return;
+                }
+                other => panic!("{:?}", other),
+            }
+        }
+        panic!("didn't abort");
+    }
+
+    #[test]
+    fn aborts_when_it_never_oscillates() {
+        let mut room = Room::bedroom();
+        // Heating too weak to ever reach the target
+        room.heating_rate = 0.05;
+
+        let (step, _) = tune(&mut room, 70.0, Instant::now());
+        assert!(matches!(step, Step::Abort(_)), "{:?}", step);
+    }
+}
@@ -1,34 +1,61 @@
-use crate::display::Display;
-use rppal::gpio::{Gpio, InputPin, Trigger};
+use crate::error::Result;
+use crate::{Event, TARGET_RANGE};
+use rppal::gpio::{Gpio, InputPin, Level, Trigger};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::time::delay_until;
+use tracing::{debug, info_span, warn, Instrument};
+
+mod encoder;
+mod input;
+
+use encoder::{Channel, Quadrature};
+use input::ButtonInput;
+pub(crate) use input::{ButtonEvent, Timings};
+
+/// Raw edges waiting for the input task, plenty for a bouncy press.
+const EDGE_BUFFER: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Button {
+    Up,
+    Down,
+}
+
+impl Button {
+    fn other(self) -> Self {
+        match self {
+            Button::Up => Button::Down,
+            Button::Down => Button::Up,
+        }
+    }
+}
+
+/// A pin level change, timestamped as soon as the interrupt arrives.
+type Edge = (Button, bool, Instant);
 
 pub(crate) struct ButtonHandler {
     _pins: Vec<InputPin>,
 }
 
 impl ButtonHandler {
+    /// Watch both edges of the button pins, sending debounced `Event::Button`s on `events_tx`.
     pub(crate) fn new(
         gpio: &Gpio,
         up_pin: u8,
         down_pin: u8,
-        lcd: Display,
-    ) -> Result<Self, Box<dyn std::error::Error>> {
-        let mut up = gpio.get(up_pin)?.into_input_pulldown();
-
-        {
-            let lcd = lcd.clone();
-            up.set_async_interrupt(Trigger::RisingEdge, move |level| {
-                eprintln!("Got up interrupt {:?}", level);
+        timings: Timings,
+        events_tx: Sender<Event>,
+    ) -> Result<Self> {
+        let (edges_tx, edges_rx) = mpsc::channel(EDGE_BUFFER);
 
-                lcd.backlight_off();
-            })?;
-        }
+        let mut up = gpio.get(up_pin)?.into_input_pulldown();
+        up.set_async_interrupt(Trigger::Both, edge_handler(Button::Up, edges_tx.clone()))?;
 
         let mut down = gpio.get(down_pin)?.into_input_pulldown();
+        down.set_async_interrupt(Trigger::Both, edge_handler(Button::Down, edges_tx))?;
 
-        down.set_async_interrupt(Trigger::RisingEdge, move |level| {
-            eprintln!("Got down interrupt {:?}", level);
-            lcd.backlight_on();
-        })?;
+        tokio::spawn(input_loop(edges_rx, events_tx, timings).instrument(info_span!("buttons")));
 
         let handler = Self {
             _pins: vec![up, down],
@@ -37,3 +64,274 @@ impl ButtonHandler {
         Ok(handler)
     }
 }
+
+fn edge_handler(button: Button, mut edges_tx: Sender<Edge>) -> impl FnMut(Level) + Send + 'static {
+    move |level| {
+        let now = Instant::now();
+
+        // Interrupts arrive on rppal's own thread, so we can't wait for room in the channel
+        if let Err(e) = edges_tx.try_send((button, level == Level::High, now)) {
+            let _span = info_span!("buttons").entered();
+            warn!(?button, error = %e, "Dropped button edge");
+        }
+    }
+}
+
+/// Run raw edges through the debouncer, forwarding the resulting button events.
+async fn input_loop(mut edges_rx: Receiver<Edge>, mut events_tx: Sender<Event>, timings: Timings) {
+    let mut input = ButtonInput::new(timings);
+
+    loop {
+        let edge = match input.next_deadline() {
+            Some(deadline) => tokio::select! {
+                edge = edges_rx.recv() => Some(edge),
+                _ = delay_until(deadline.into()) => None,
+            },
+            None => Some(edges_rx.recv().await),
+        };
+
+        let events = match edge {
+            Some(Some((button, pressed, at))) => input.edge(button, pressed, at),
+            // Both pins are gone, nothing more will arrive
+            Some(None) => return,
+            None => input.poll(Instant::now()),
+        };
+
+        for event in events {
+            debug!(?event, "Button event");
+            if events_tx.send(Event::Button(event)).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// A level change from the encoder, the switch timestamped for debouncing.
+#[derive(Debug)]
+enum EncoderEdge {
+    Turn(Channel, bool),
+    Switch(bool, Instant),
+}
+
+/// Rotary encoder with a push switch, as an alternative to the up/down buttons.
+///
+/// Turning gives the same short presses as the buttons, clockwise for up. Pressing the switch
+/// selects and holding it goes back. All three pins are expected to switch to ground.
+pub(crate) struct EncoderHandler {
+    _pins: Vec<InputPin>,
+}
+
+impl EncoderHandler {
+    pub(crate) fn new(
+        gpio: &Gpio,
+        a_pin: u8,
+        b_pin: u8,
+        switch_pin: u8,
+        steps_per_detent: u8,
+        timings: Timings,
+        events_tx: Sender<Event>,
+    ) -> Result<Self> {
+        let (edges_tx, edges_rx) = mpsc::channel(EDGE_BUFFER);
+
+        let mut a = gpio.get(a_pin)?.into_input_pullup();
+        let mut b = gpio.get(b_pin)?.into_input_pullup();
+        let decoder = Quadrature::new(steps_per_detent, a.is_high(), b.is_high());
+        a.set_async_interrupt(Trigger::Both, turn_handler(Channel::A, edges_tx.clone()))?;
+        b.set_async_interrupt(Trigger::Both, turn_handler(Channel::B, edges_tx.clone()))?;
+
+        let mut switch = gpio.get(switch_pin)?.into_input_pullup();
+        switch.set_async_interrupt(Trigger::Both, switch_handler(edges_tx))?;
+
+        tokio::spawn(
+            encoder_loop(edges_rx, events_tx, decoder, timings).instrument(info_span!("encoder")),
+        );
+
+        Ok(Self {
+            _pins: vec![a, b, switch],
+        })
+    }
+}
+
+fn turn_handler(
+    channel: Channel,
+    mut edges_tx: Sender<EncoderEdge>,
+) -> impl FnMut(Level) + Send + 'static {
+    move |level| {
+        if let Err(e) = edges_tx.try_send(EncoderEdge::Turn(channel, level == Level::High)) {
+            let _span = info_span!("encoder").entered();
+            warn!(?channel, error = %e, "Dropped encoder edge");
+        }
+    }
+}
+
+fn switch_handler(mut edges_tx: Sender<EncoderEdge>) -> impl FnMut(Level) + Send + 'static {
+    move |level| {
+        let now = Instant::now();
+
+        if let Err(e) = edges_tx.try_send(EncoderEdge::Switch(level == Level::Low, now)) {
+            let _span = info_span!("encoder").entered();
+            warn!(error = %e, "Dropped encoder switch edge");
+        }
+    }
+}
+
+/// Decode encoder edges, debouncing the switch like a button.
+async fn encoder_loop(
+    mut edges_rx: Receiver<EncoderEdge>,
+    mut events_tx: Sender<Event>,
+    mut decoder: Quadrature,
+    timings: Timings,
+) {
+    let mut switch = ButtonInput::new(timings);
+
+    loop {
+        let edge = match switch.next_deadline() {
+            Some(deadline) => tokio::select! {
+                edge = edges_rx.recv() => Some(edge),
+                _ = delay_until(deadline.into()) => None,
+            },
+            None => Some(edges_rx.recv().await),
+        };
+
+        let events = match edge {
+            Some(Some(EncoderEdge::Turn(channel, level))) => decoder
+                .edge(channel, level)
+                .map(ButtonEvent::Short)
+                .into_iter()
+                .collect(),
+            Some(Some(EncoderEdge::Switch(pressed, at))) => {
+                switch_events(switch.edge(Button::Up, pressed, at))
+            }
+            Some(None) => return,
+            None => switch_events(switch.poll(Instant::now())),
+        };
+
+        for event in events {
+            debug!(?event, "Encoder event");
+            if events_tx.send(Event::Button(event)).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// The switch is fed through as the up button, turn its presses into select and back.
+fn switch_events(events: Vec<ButtonEvent>) -> Vec<ButtonEvent> {
+    events
+        .into_iter()
+        .filter_map(|event| match event {
+            ButtonEvent::Short(_) => Some(ButtonEvent::Select),
+            ButtonEvent::Long(_) => Some(ButtonEvent::Combo),
+            _ => None,
+        })
+        .collect()
+}
+
+/// What the controller should do in response to the target editor.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum EditorAction {
+    /// Show this pending target while the user is still pressing buttons
+    Editing(f32),
+    /// No presses for a while, apply the pending target
+    Commit(f32),
+}
+
+/// Turns up/down presses into target changes.
+///
+/// Presses step a pending target which is committed once the buttons have been left alone for
+/// the commit delay.
+#[derive(Debug)]
+pub(crate) struct TargetEditor {
+    increment: f32,
+    commit_delay: Duration,
+    pending: Option<(f32, Instant)>,
+}
+
+impl TargetEditor {
+    pub(crate) fn new(increment: f32, commit_delay: Duration) -> Self {
+        Self {
+            increment,
+            commit_delay,
+            pending: None,
+        }
+    }
+
+    pub(crate) fn press(&mut self, button: Button, target: f32, now: Instant) -> EditorAction {
+        let current = self.pending.map_or(target, |(pending, _)| pending);
+        let pending = match button {
+            Button::Up => current + self.increment,
+            Button::Down => current - self.increment,
+        }
+        .clamp(*TARGET_RANGE.start(), *TARGET_RANGE.end());
+        self.pending = Some((pending, now + self.commit_delay));
+
+        EditorAction::Editing(pending)
+    }
+
+    /// Check for an expired commit timer, returning what to do about it.
+    pub(crate) fn poll(&mut self, now: Instant) -> Option<EditorAction> {
+        match self.pending {
+            Some((pending, commit_at)) if now >= commit_at => {
+                self.pending = None;
+                Some(EditorAction::Commit(pending))
+            }
+            _ => None,
+        }
+    }
+
+    /// When `poll` next needs to be called.
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        self.pending.map(|(_, commit_at)| commit_at)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMMIT_DELAY: Duration = Duration::from_secs(3);
+
+    fn editor() -> TargetEditor {
+        TargetEditor::new(0.5, COMMIT_DELAY)
+    }
+
+    #[test]
+    fn steps_and_commits_after_delay() {
+        let mut editor = editor();
+        let now = Instant::now();
+
+        assert_eq!(
+            editor.press(Button::Up, 70.0, now),
+            EditorAction::Editing(70.5)
+        );
+        assert_eq!(
+            editor.press(Button::Up, 70.0, now + Duration::from_secs(1)),
+            EditorAction::Editing(71.0)
+        );
+        assert_eq!(
+            editor.press(Button::Down, 70.0, now + Duration::from_secs(2)),
+            EditorAction::Editing(70.5)
+        );
+
+        let last_press = now + Duration::from_secs(2);
+        assert_eq!(editor.next_deadline(), Some(last_press + COMMIT_DELAY));
+        assert_eq!(editor.poll(last_press + Duration::from_secs(1)), None);
+        assert_eq!(
+            editor.poll(last_press + COMMIT_DELAY),
+            Some(EditorAction::Commit(70.5))
+        );
+        assert_eq!(editor.poll(last_press + COMMIT_DELAY), None);
+        assert_eq!(editor.next_deadline(), None);
+    }
+
+    #[test]
+    fn stops_at_the_target_range() {
+        let mut editor = editor();
+        let now = Instant::now();
+
+        assert_eq!(
+            editor.press(Button::Up, *TARGET_RANGE.end(), now),
+            EditorAction::Editing(*TARGET_RANGE.end())
+        );
+    }
+}
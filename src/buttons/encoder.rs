@@ -0,0 +1,138 @@
+use super::Button;
+
+/// Quadrature steps for each `(previous << 2) | current` pair of `(a << 1) | b` states.
+///
+/// Transitions that skip a state (both lines changing at once) can't tell us a direction and
+/// count as nothing.
+const TRANSITIONS: [i8; 16] = [0, -1, 1, 0, 1, 0, 0, -1, -1, 0, 0, 1, 0, 1, -1, 0];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Channel {
+    A,
+    B,
+}
+
+/// Decodes the two quadrature lines of a rotary encoder into up/down steps.
+///
+/// Contact bounce on one line just steps back and forth, so only a full detent's worth of steps
+/// in one direction turns into a press. Clockwise (A leading B) is up.
+#[derive(Debug)]
+pub(crate) struct Quadrature {
+    steps_per_detent: i8,
+    state: u8,
+    count: i8,
+}
+
+impl Quadrature {
+    pub(crate) fn new(steps_per_detent: u8, a: bool, b: bool) -> Self {
+        Self {
+            steps_per_detent: steps_per_detent.max(1) as i8,
+            state: encode(a, b),
+            count: 0,
+        }
+    }
+
+    pub(crate) fn edge(&mut self, channel: Channel, level: bool) -> Option<Button> {
+        let (a, b) = match channel {
+            Channel::A => (level, self.state & 0b01 != 0),
+            Channel::B => (self.state & 0b10 != 0, level),
+        };
+        let state = encode(a, b);
+
+        self.count += TRANSITIONS[((self.state << 2) | state) as usize];
+        self.state = state;
+
+        if self.count >= self.steps_per_detent {
+            self.count = 0;
+            Some(Button::Up)
+        } else if self.count <= -self.steps_per_detent {
+            self.count = 0;
+            Some(Button::Down)
+        } else {
+            None
+        }
+    }
+}
+
+fn encode(a: bool, b: bool) -> u8 {
+    ((a as u8) << 1) | b as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(decoder: &mut Quadrature, edges: &[(Channel, bool)]) -> Vec<Button> {
+        edges
+            .iter()
+            .filter_map(|&(channel, level)| decoder.edge(channel, level))
+            .collect()
+    }
+
+    const CLOCKWISE: [(Channel, bool); 4] = [
+        (Channel::A, false),
+        (Channel::B, false),
+        (Channel::A, true),
+        (Channel::B, true),
+    ];
+
+    #[test]
+    fn full_cycle_is_one_detent() {
+        let mut decoder = Quadrature::new(4, true, true);
+
+        assert_eq!(run(&mut decoder, &CLOCKWISE), vec![Button::Up]);
+
+        let counter_clockwise = [
+            (Channel::B, false),
+            (Channel::A, false),
+            (Channel::B, true),
+            (Channel::A, true),
+        ];
+        assert_eq!(run(&mut decoder, &counter_clockwise), vec![Button::Down]);
+    }
+
+    #[test]
+    fn bounce_cancels_out() {
+        let mut decoder = Quadrature::new(4, true, true);
+        let edges = [
+            (Channel::A, false),
+            (Channel::A, true),
+            (Channel::A, false),
+            (Channel::B, false),
+            (Channel::B, true),
+            (Channel::B, false),
+            (Channel::A, true),
+            (Channel::B, true),
+        ];
+
+        assert_eq!(run(&mut decoder, &edges), vec![Button::Up]);
+    }
+
+    #[test]
+    fn partial_turn_and_back_does_nothing() {
+        let mut decoder = Quadrature::new(4, true, true);
+        let edges = [
+            (Channel::A, false),
+            (Channel::B, false),
+            (Channel::B, true),
+            (Channel::A, true),
+        ];
+
+        assert_eq!(run(&mut decoder, &edges), vec![]);
+    }
+
+    #[test]
+    fn half_step_encoders() {
+        let mut decoder = Quadrature::new(2, true, true);
+
+        assert_eq!(run(&mut decoder, &CLOCKWISE), vec![Button::Up, Button::Up]);
+    }
+
+    #[test]
+    fn repeated_levels_are_ignored() {
+        let mut decoder = Quadrature::new(1, true, true);
+        let edges = [(Channel::A, true), (Channel::B, true), (Channel::A, false)];
+
+        assert_eq!(run(&mut decoder, &edges), vec![Button::Up]);
+    }
+}
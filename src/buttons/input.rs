@@ -0,0 +1,319 @@
+use super::Button;
+use std::time::{Duration, Instant};
+
+const BUTTONS: [Button; 2] = [Button::Up, Button::Down];
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Timings {
+    /// How long a level has to be steady before it's believed
+    pub(crate) debounce: Duration,
+    /// Hold time before a press counts as long
+    pub(crate) long_press: Duration,
+    /// Time between repeats while a button stays held after a long press
+    pub(crate) repeat_interval: Duration,
+    /// Maximum gap between pressing the two buttons for it to count as a combo
+    pub(crate) combo_window: Duration,
+}
+
+/// Clean, debounced button events.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ButtonEvent {
+    /// Pressed and released before the long press time
+    Short(Button),
+    /// Held for the long press time, fired while still held
+    Long(Button),
+    /// Still held after a long press
+    Repeat(Button),
+    /// Both buttons pressed together, or a long press of the encoder switch
+    Combo,
+    /// A press of the encoder switch
+    Select,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Last level seen on the pin and when it changed, if not yet settled
+    raw: bool,
+    raw_changed: Option<Instant>,
+    /// When the debounced press started, `None` while released
+    pressed_at: Option<Instant>,
+    long_sent: bool,
+    next_repeat: Option<Instant>,
+    /// Part of a combo, so no short/long events until released
+    combo: bool,
+}
+
+/// Turns raw pin edges into button events.
+///
+/// Feed it every edge with `edge` and call `poll` by `next_deadline`, timings are all based on
+/// the timestamps passed in so it can be driven by synthetic edges.
+#[derive(Debug)]
+pub(crate) struct ButtonInput {
+    timings: Timings,
+    up: State,
+    down: State,
+}
+
+impl ButtonInput {
+    pub(crate) fn new(timings: Timings) -> Self {
+        Self {
+            timings,
+            up: State::default(),
+            down: State::default(),
+        }
+    }
+
+    pub(crate) fn edge(&mut self, button: Button, pressed: bool, now: Instant) -> Vec<ButtonEvent> {
+        let state = self.state_mut(button);
+        if state.raw != pressed {
+            state.raw = pressed;
+            state.raw_changed = Some(now);
+        }
+
+        self.poll(now)
+    }
+
+    pub(crate) fn poll(&mut self, now: Instant) -> Vec<ButtonEvent> {
+        let mut events = Vec::new();
+
+        for &button in &BUTTONS {
+            self.settle(button, now, &mut events);
+        }
+        for &button in &BUTTONS {
+            self.check_held(button, now, &mut events);
+        }
+
+        events
+    }
+
+    /// When `poll` next needs to be called, if anything is pending.
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        BUTTONS
+            .iter()
+            .flat_map(|&button| {
+                let state = self.state(button);
+                let settle = state.raw_changed.map(|at| at + self.timings.debounce);
+                let held = match state.pressed_at {
+                    Some(_) if state.combo => None,
+                    Some(at) if !state.long_sent => Some(at + self.timings.long_press),
+                    Some(_) => state.next_repeat,
+                    None => None,
+                };
+
+                settle.into_iter().chain(held)
+            })
+            .min()
+    }
+
+    fn settle(&mut self, button: Button, now: Instant, events: &mut Vec<ButtonEvent>) {
+        let timings = self.timings;
+        let state = self.state_mut(button);
+
+        let changed_at = match state.raw_changed {
+            Some(at) if now >= at + timings.debounce => at,
+            _ => return,
+        };
+        state.raw_changed = None;
+
+        // Bounced back to where it started
+        if state.raw == state.pressed_at.is_some() {
+            return;
+        }
+
+        if state.raw {
+            *state = State {
+                raw: true,
+                pressed_at: Some(changed_at),
+                ..State::default()
+            };
+
+            let other = self.state_mut(button.other());
+            let combo = match other.pressed_at {
+                Some(other_at) => {
+                    !other.long_sent
+                        && !other.combo
+                        && changed_at.saturating_duration_since(other_at) <= timings.combo_window
+                }
+                None => false,
+            };
+
+            if combo {
+                other.combo = true;
+                self.state_mut(button).combo = true;
+                events.push(ButtonEvent::Combo);
+            }
+        } else {
+            state.pressed_at = None;
+            state.next_repeat = None;
+
+            if !state.combo && !state.long_sent {
+                events.push(ButtonEvent::Short(button));
+            }
+        }
+    }
+
+    fn check_held(&mut self, button: Button, now: Instant, events: &mut Vec<ButtonEvent>) {
+        let timings = self.timings;
+        let state = self.state_mut(button);
+
+        let pressed_at = match state.pressed_at {
+            Some(at) if !state.combo => at,
+            _ => return,
+        };
+
+        if !state.long_sent {
+            if now >= pressed_at + timings.long_press {
+                state.long_sent = true;
+                state.next_repeat = Some(now + timings.repeat_interval);
+                events.push(ButtonEvent::Long(button));
+            }
+        } else if let Some(next_repeat) = state.next_repeat {
+            if now >= next_repeat {
+                state.next_repeat = Some(now + timings.repeat_interval);
+                events.push(ButtonEvent::Repeat(button));
+            }
+        }
+    }
+
+    fn state(&self, button: Button) -> &State {
+        match button {
+            Button::Up => &self.up,
+            Button::Down => &self.down,
+        }
+    }
+
+    fn state_mut(&mut self, button: Button) -> &mut State {
+        match button {
+            Button::Up => &mut self.up,
+            Button::Down => &mut self.down,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMINGS: Timings = Timings {
+        debounce: Duration::from_millis(20),
+        long_press: Duration::from_millis(800),
+        repeat_interval: Duration::from_millis(200),
+        combo_window: Duration::from_millis(150),
+    };
+
+    fn ms(start: Instant, millis: u64) -> Instant {
+        start + Duration::from_millis(millis)
+    }
+
+    /// Apply edges then poll at every deadline up to `until`, collecting all events.
+    fn run(
+        input: &mut ButtonInput,
+        start: Instant,
+        edges: &[(u64, Button, bool)],
+        until: u64,
+    ) -> Vec<ButtonEvent> {
+        let mut events = Vec::new();
+        let end = ms(start, until);
+
+        for &(at, button, pressed) in edges {
+            let at = ms(start, at);
+            while let Some(deadline) = input.next_deadline().filter(|&d| d <= at) {
+                events.extend(input.poll(deadline));
+            }
+            events.extend(input.edge(button, pressed, at));
+        }
+
+        while let Some(deadline) = input.next_deadline().filter(|&d| d <= end) {
+            events.extend(input.poll(deadline));
+        }
+
+        events
+    }
+
+    #[test]
+    fn bouncy_press_is_one_short_press() {
+        let mut input = ButtonInput::new(TIMINGS);
+        let start = Instant::now();
+        let edges = [
+            (0, Button::Up, true),
+            (2, Button::Up, false),
+            (3, Button::Up, true),
+            (5, Button::Up, false),
+            (6, Button::Up, true),
+            (200, Button::Up, false),
+            (201, Button::Up, true),
+            (203, Button::Up, false),
+        ];
+
+        let events = run(&mut input, start, &edges, 1000);
+
+        assert_eq!(events, vec![ButtonEvent::Short(Button::Up)]);
+    }
+
+    #[test]
+    fn glitch_shorter_than_debounce_is_ignored() {
+        let mut input = ButtonInput::new(TIMINGS);
+        let start = Instant::now();
+        let edges = [(0, Button::Down, true), (5, Button::Down, false)];
+
+        let events = run(&mut input, start, &edges, 1000);
+
+        assert_eq!(events, vec![]);
+    }
+
+    #[test]
+    fn held_button_long_presses_then_repeats() {
+        let mut input = ButtonInput::new(TIMINGS);
+        let start = Instant::now();
+        let edges = [(0, Button::Down, true), (1300, Button::Down, false)];
+
+        let events = run(&mut input, start, &edges, 2000);
+
+        assert_eq!(
+            events,
+            vec![
+                ButtonEvent::Long(Button::Down),
+                ButtonEvent::Repeat(Button::Down),
+                ButtonEvent::Repeat(Button::Down),
+            ]
+        );
+    }
+
+    #[test]
+    fn both_buttons_together_are_a_combo() {
+        let mut input = ButtonInput::new(TIMINGS);
+        let start = Instant::now();
+        let edges = [
+            (0, Button::Up, true),
+            (100, Button::Down, true),
+            (1500, Button::Up, false),
+            (1600, Button::Down, false),
+        ];
+
+        let events = run(&mut input, start, &edges, 2000);
+
+        assert_eq!(events, vec![ButtonEvent::Combo]);
+    }
+
+    #[test]
+    fn presses_too_far_apart_are_not_a_combo() {
+        let mut input = ButtonInput::new(TIMINGS);
+        let start = Instant::now();
+        let edges = [
+            (0, Button::Up, true),
+            (400, Button::Down, true),
+            (500, Button::Down, false),
+            (600, Button::Up, false),
+        ];
+
+        let events = run(&mut input, start, &edges, 1000);
+
+        assert_eq!(
+            events,
+            vec![
+                ButtonEvent::Short(Button::Down),
+                ButtonEvent::Short(Button::Up)
+            ]
+        );
+    }
+}
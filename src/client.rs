@@ -1,67 +1,102 @@
+use crate::error::{Error, Result};
+use crate::metrics::Metrics;
+use crate::supervisor::supervise;
 use futures::stream::StreamExt;
-use rumq_client::{eventloop, MqttOptions, Notification, QoS, Request, Subscribe};
+use rumq_client::{eventloop, MqttEventLoop, MqttOptions, Notification, QoS, Request, Subscribe};
 use std::env;
 use std::net::SocketAddr;
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::{watch, Mutex};
 use tokio::time;
+use tracing::{error, info, info_span, warn, Instrument};
 
 pub(crate) async fn connect(
     address: &str,
     topics: Vec<&'static str>,
-) -> (Sender<Request>, Receiver<Notification>) {
-    let address: SocketAddr = address.parse().unwrap();
+    metrics: Metrics,
+) -> Result<(
+    Sender<Request>,
+    Receiver<Notification>,
+    watch::Receiver<bool>,
+)> {
+    let address: SocketAddr = address
+        .parse()
+        .map_err(|e| Error::Config(format!("invalid MQTT address {}: {}", address, e)))?;
     let mut mqtt_options = MqttOptions::new("thermostat", address.ip().to_string(), address.port());
     mqtt_options
         .set_clean_session(true)
         .set_keep_alive(5)
-        .set_credentials(
-            env::var("MQTT_USER").expect("MQTT_USER environment variable lookup failed"),
-            env::var("MQTT_PASSWORD").expect("MQTT_PASSWORD environment variable lookup failed"),
-        );
+        .set_credentials(env_var("MQTT_USER")?, env_var("MQTT_PASSWORD")?);
     let (requests_tx, requests_rx) = channel(5);
     let (notifications_tx, notifications_rx) = channel(10);
+    let (connected_tx, connected_rx) = watch::channel(false);
 
-    tokio::spawn(reconnect_loop(
-        mqtt_options,
-        requests_tx.clone(),
-        requests_rx,
-        topics,
-        notifications_tx,
-    ));
+    // The event loop owns the request receiver, so it's shared across restarts
+    let event_loop = Arc::new(Mutex::new(eventloop(mqtt_options, requests_rx)));
+    let connected_tx = Arc::new(connected_tx);
+    {
+        let requests_tx = requests_tx.clone();
+        supervise("mqtt client", move || {
+            reconnect_loop(
+                event_loop.clone(),
+                requests_tx.clone(),
+                topics.clone(),
+                notifications_tx.clone(),
+                connected_tx.clone(),
+                metrics.clone(),
+            )
+            .instrument(info_span!("mqtt"))
+        });
+    }
+
+    Ok((requests_tx, notifications_rx, connected_rx))
+}
 
-    (requests_tx, notifications_rx)
+fn env_var(name: &str) -> Result<String> {
+    env::var(name).map_err(|e| Error::Config(format!("{} lookup failed: {}", name, e)))
 }
 
 async fn reconnect_loop(
-    mqtt_options: MqttOptions,
+    event_loop: Arc<Mutex<MqttEventLoop>>,
     requests_tx: Sender<Request>,
-    requests_rx: Receiver<Request>,
     topics: Vec<&'static str>,
     mut notifications_tx: Sender<Notification>,
-) {
-    let mut event_loop = eventloop(mqtt_options, requests_rx);
+    connected_tx: Arc<watch::Sender<bool>>,
+    metrics: Metrics,
+) -> Result<()> {
+    let mut event_loop = event_loop.lock().await;
 
     loop {
         match event_loop.connect().await {
             Ok(mut stream) => {
-                println!("Connected to broker");
+                info!("Connected to broker");
+                let _ = connected_tx.broadcast(true);
                 for &topic in &topics {
                     let mut requests_tx = requests_tx.clone();
                     tokio::spawn(async move {
                         let subscription = Subscribe::new(topic, QoS::AtLeastOnce);
-                        requests_tx.send(subscription.into()).await.unwrap();
+                        if requests_tx.send(subscription.into()).await.is_err() {
+                            error!(topic, "Failed to subscribe, request channel closed");
+                        }
                     });
                 }
 
                 while let Some(item) = stream.next().await {
-                    notifications_tx.send(item).await.unwrap();
+                    if notifications_tx.send(item).await.is_err() {
+                        let _ = connected_tx.broadcast(false);
+                        return Err(Error::ChannelClosed("mqtt notifications"));
+                    }
                 }
+
+                let _ = connected_tx.broadcast(false);
             }
-            Err(e) => eprintln!("Got error trying to connect: {}", e),
+            Err(e) => warn!(error = %e, "Got error trying to connect"),
         };
 
         time::delay_for(Duration::from_secs(5)).await;
-        println!("Attempting to reconnect MQTT");
+        info!("Attempting to reconnect MQTT");
+        metrics.mqtt_reconnect();
     }
 }
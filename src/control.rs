@@ -0,0 +1,466 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+use std::io::prelude::*;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+#[cfg(test)]
+pub(crate) mod room;
+
+/// How the relay is driven from the temperature.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Algorithm {
+    /// On below the target less the variance, off above it plus the variance
+    Hysteresis,
+    /// A PID duty cycle, applied by switching the relay on for that fraction of each cycle
+    Pid,
+}
+
+impl Algorithm {
+    fn as_str(self) -> &'static str {
+        match self {
+            Algorithm::Hysteresis => "hysteresis",
+            Algorithm::Pid => "pid",
+        }
+    }
+}
+
+/// PID gains. Errors are in Fahrenheit and time in minutes, the output is a duty cycle of 0 to 1.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Gains {
+    pub(crate) kp: f32,
+    pub(crate) ki: f32,
+    pub(crate) kd: f32,
+}
+
+/// Weather compensation, raising the target a little when it's cold outside.
+///
+/// A cold room's walls and windows make it feel cooler than the air temperature says, and the
+/// heating takes longer to catch up with any drop.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub(crate) struct HeatingCurve {
+    /// Outdoor temperature below which the target is raised
+    pub(crate) from: f32,
+    /// Fahrenheit added per degree colder than `from`
+    pub(crate) slope: f32,
+    /// Most the target is raised
+    pub(crate) max: f32,
+}
+
+impl HeatingCurve {
+    /// How far to raise the target, nothing without an outdoor temperature.
+    ///
+    /// Rounded to quarter degrees, so the target doesn't move with every outdoor reading.
+    pub(crate) fn offset(&self, outdoor: Option<f32>) -> f32 {
+        match outdoor {
+            Some(outdoor) if outdoor.is_finite() => {
+                let offset = ((self.from - outdoor) * self.slope).clamp(0.0, self.max);
+                (offset * 4.0).round() / 4.0
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+/// Tuning for the heating control, from the control file.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub(crate) struct ControlConfig {
+    pub(crate) algorithm: Algorithm,
+    pub(crate) gains: Gains,
+    /// Length of one time-proportioning cycle, the PID runs once at the start of each
+    pub(crate) cycle_secs: u64,
+    /// Shorter on periods are skipped, so the boiler isn't fired for a few seconds
+    pub(crate) min_on_secs: u64,
+    /// Shorter off periods are skipped, the relay stays on for the whole cycle instead
+    pub(crate) min_off_secs: u64,
+    pub(crate) curve: HeatingCurve,
+}
+
+impl Default for ControlConfig {
+    fn default() -> Self {
+        Self {
+            algorithm: Algorithm::Hysteresis,
+            gains: Gains {
+                kp: 0.5,
+                ki: 0.01,
+                kd: 0.0,
+            },
+            cycle_secs: 10 * 60,
+            min_on_secs: 2 * 60,
+            min_off_secs: 2 * 60,
+            // Off until a slope is set
+            curve: HeatingCurve {
+                from: 32.0,
+                slope: 0.0,
+                max: 2.0,
+            },
+        }
+    }
+}
+
+impl ControlConfig {
+    pub(crate) fn cycle(&self) -> Duration {
+        Duration::from_secs(self.cycle_secs)
+    }
+
+    pub(crate) fn min_on(&self) -> Duration {
+        Duration::from_secs(self.min_on_secs)
+    }
+
+    pub(crate) fn min_off(&self) -> Duration {
+        Duration::from_secs(self.min_off_secs)
+    }
+
+    pub(crate) fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut file = File::open(path)?;
+        let mut contents = String::new();
+
+        file.read_to_string(&mut contents)?;
+
+        Ok(contents.parse()?)
+    }
+
+    pub(crate) fn save(&self, path: &str) -> Result<(), std::io::Error> {
+        let mut file = File::create(path)?;
+        file.write_all(self.to_string().as_bytes())
+    }
+}
+
+/// One `key value` pair per line, missing keys keep their default.
+impl FromStr for ControlConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut config = ControlConfig::default();
+
+        for line in s.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let mut parts = line.split_whitespace();
+            let key = parts.next().unwrap_or_default();
+            let value = parts
+                .next()
+                .ok_or_else(|| format!("missing value in control line {:?}", line))?;
+            let invalid = || format!("invalid value in control line {:?}", line);
+
+            match key {
+                "algorithm" => {
+                    config.algorithm = match value {
+                        "hysteresis" => Algorithm::Hysteresis,
+                        "pid" => Algorithm::Pid,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "kp" => config.gains.kp = value.parse().map_err(|_| invalid())?,
+                "ki" => config.gains.ki = value.parse().map_err(|_| invalid())?,
+                "kd" => config.gains.kd = value.parse().map_err(|_| invalid())?,
+                "cycle" => config.cycle_secs = value.parse().map_err(|_| invalid())?,
+                "min_on" => config.min_on_secs = value.parse().map_err(|_| invalid())?,
+                "min_off" => config.min_off_secs = value.parse().map_err(|_| invalid())?,
+                "curve_from" => config.curve.from = value.parse().map_err(|_| invalid())?,
+                "curve_slope" => config.curve.slope = value.parse().map_err(|_| invalid())?,
+                "curve_max" => config.curve.max = value.parse().map_err(|_| invalid())?,
+                _ => return Err(format!("unknown control setting {:?}", key)),
+            }
+        }
+
+        if config.cycle_secs == 0 || config.min_on_secs + config.min_off_secs > config.cycle_secs {
+            return Err("minimum on and off times must fit in the cycle".to_string());
+        }
+        if config.curve.slope < 0.0 || config.curve.max < 0.0 {
+            return Err("heating curve can only raise the target".to_string());
+        }
+
+        Ok(config)
+    }
+}
+
+impl fmt::Display for ControlConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "algorithm {}", self.algorithm.as_str())?;
+        writeln!(f, "kp {}", self.gains.kp)?;
+        writeln!(f, "ki {}", self.gains.ki)?;
+        writeln!(f, "kd {}", self.gains.kd)?;
+        writeln!(f, "cycle {}", self.cycle_secs)?;
+        writeln!(f, "min_on {}", self.min_on_secs)?;
+        writeln!(f, "min_off {}", self.min_off_secs)?;
+        writeln!(f, "curve_from {}", self.curve.from)?;
+        writeln!(f, "curve_slope {}", self.curve.slope)?;
+        writeln!(f, "curve_max {}", self.curve.max)
+    }
+}
+
+/// The controller's terms for one cycle, published so the gains can be tuned.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub(crate) struct PidOutput {
+    pub(crate) error: f32,
+    pub(crate) p: f32,
+    pub(crate) i: f32,
+    pub(crate) d: f32,
+    /// Sum of the terms, limited to 0 to 1
+    pub(crate) duty: f32,
+}
+
+/// PID controller for heating only, so the output never goes below zero.
+#[derive(Debug, Default)]
+pub(crate) struct Pid {
+    /// Error integrated over minutes
+    integral: f32,
+    last_temperature: Option<f32>,
+}
+
+impl Pid {
+    /// Forget the history, e.g. once heating is switched off.
+    pub(crate) fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Work out the duty cycle for the next `elapsed` of heating.
+    ///
+    /// The derivative is on the temperature rather than the error, so changing the target doesn't
+    /// kick the output. The integral never contributes more than a full duty cycle.
+    pub(crate) fn update(
+        &mut self,
+        gains: &Gains,
+        target: f32,
+        temperature: f32,
+        elapsed: Duration,
+    ) -> PidOutput {
+        let minutes = elapsed.as_secs_f32() / 60.0;
+        let error = target - temperature;
+
+        let p = gains.kp * error;
+        let d = match self.last_temperature {
+            Some(last) if minutes > 0.0 => -gains.kd * (temperature - last) / minutes,
+            _ => 0.0,
+        };
+        self.last_temperature = Some(temperature);
+
+        // Only integrate as far as it takes to saturate the output, so it doesn't wind up while
+        // the room is far from the target
+        let integral = self.integral + error * minutes;
+        self.integral = if gains.ki > 0.0 {
+            let saturated = ((1.0 - p - d) / gains.ki).max(self.integral);
+            integral.min(saturated).max(0.0).min(1.0 / gains.ki)
+        } else {
+            0.0
+        };
+
+        let i = gains.ki * self.integral;
+        let duty = (p + i + d).clamp(0.0, 1.0);
+
+        PidOutput {
+            error,
+            p,
+            i,
+            d,
+            duty,
+        }
+    }
+}
+
+/// Applies a duty cycle by switching the relay on at the start of each cycle and off part way
+/// through.
+#[derive(Debug, Default)]
+pub(crate) struct DutyCycle {
+    /// Start of the current cycle, and how long the relay is on for in it
+    current: Option<(Instant, Duration)>,
+}
+
+impl DutyCycle {
+    /// A new cycle is due, the PID should be run and the result passed to `start`.
+    pub(crate) fn due(&self, config: &ControlConfig, now: Instant) -> bool {
+        match self.current {
+            Some((started, _)) => now >= started + config.cycle(),
+            None => true,
+        }
+    }
+
+    /// Time since the last cycle started, for the PID's integral and derivative.
+    pub(crate) fn elapsed(&self, now: Instant) -> Duration {
+        self.current.map_or(Duration::from_secs(0), |(started, _)| {
+            now.saturating_duration_since(started)
+        })
+    }
+
+    /// Start a cycle at `duty`, returning how long the relay will be on.
+    ///
+    /// On times shorter than the minimum are dropped and off times shorter than the minimum are
+    /// filled in, so the relay never switches faster than the heating can take.
+    pub(crate) fn start(&mut self, config: &ControlConfig, duty: f32, now: Instant) -> Duration {
+        let cycle = config.cycle();
+        let on = cycle.mul_f32(duty.clamp(0.0, 1.0));
+
+        let on = if on < config.min_on() {
+            Duration::from_secs(0)
+        } else if cycle - on < config.min_off() {
+            cycle
+        } else {
+            on
+        };
+
+        self.current = Some((now, on));
+        on
+    }
+
+    /// Whether the relay should be on at `now`.
+    pub(crate) fn relay_on(&self, now: Instant) -> bool {
+        matches!(self.current, Some((started, on)) if now < started + on)
+    }
+
+    /// When the relay next needs switching, or the next cycle starts.
+    pub(crate) fn next_switch(&self, config: &ControlConfig, now: Instant) -> Option<Instant> {
+        let (started, on) = self.current?;
+        let off_at = started + on;
+        let end = started + config.cycle();
+
+        Some(if now < off_at { off_at } else { end })
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.current = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GAINS: Gains = Gains {
+        kp: 0.5,
+        ki: 0.1,
+        kd: 2.0,
+    };
+
+    fn minutes(minutes: u64) -> Duration {
+        Duration::from_secs(minutes * 60)
+    }
+
+    #[test]
+    fn round_trips_through_text() {
+        let config = ControlConfig {
+            algorithm: Algorithm::Pid,
+            gains: Gains {
+                kp: 0.4,
+                ki: 0.02,
+                kd: 1.5,
+            },
+            cycle_secs: 900,
+            min_on_secs: 180,
+            min_off_secs: 60,
+            curve: HeatingCurve {
+                from: 40.0,
+                slope: 0.05,
+                max: 1.5,
+            },
+        };
+
+        assert_eq!(config.to_string().parse::<ControlConfig>(), Ok(config));
+        assert_eq!(
+            "algorithm pid\n"
+                .parse::<ControlConfig>()
+                .unwrap()
+                .cycle_secs,
+            600
+        );
+        assert!("algorithm bang_bang\n".parse::<ControlConfig>().is_err());
+        assert!("cycle 100\nmin_on 60\nmin_off 60\n"
+            .parse::<ControlConfig>()
+            .is_err());
+        assert!("curve_slope -0.1\n".parse::<ControlConfig>().is_err());
+    }
+
+    #[test]
+    fn heating_curve_offset() {
+        let curve = HeatingCurve {
+            from: 40.0,
+            slope: 0.05,
+            max: 1.5,
+        };
+
+        assert_eq!(curve.offset(None), 0.0);
+        assert_eq!(curve.offset(Some(f32::NAN)), 0.0);
+        assert_eq!(curve.offset(Some(50.0)), 0.0);
+        assert_eq!(curve.offset(Some(34.0)), 0.25);
+        assert_eq!(curve.offset(Some(20.0)), 1.0);
+        assert_eq!(curve.offset(Some(-20.0)), 1.5);
+    }
+
+    #[test]
+    fn pid_terms() {
+        let mut pid = Pid::default();
+
+        // First run has no derivative to go on
+        let output = pid.update(&GAINS, 70.0, 69.0, minutes(10));
+        assert_eq!(output.p, 0.5);
+        assert_eq!(output.d, 0.0);
+        assert!((output.i - 0.5).abs() < 1e-6, "{:?}", output);
+        assert_eq!(output.duty, 1.0);
+
+        // Warming up quickly brakes the output, and the saturated integral didn't wind up
+        let output = pid.update(&GAINS, 70.0, 69.5, minutes(10));
+        assert!((output.d + 0.1).abs() < 1e-6, "{:?}", output);
+        assert!(output.i <= 1.0);
+
+        let output = pid.update(&GAINS, 70.0, 71.0, minutes(10));
+        assert_eq!(output.error, -1.0);
+        assert!(output.duty < 0.5, "{:?}", output);
+    }
+
+    #[test]
+    fn integral_unwinds_quickly_after_saturation() {
+        let mut pid = Pid::default();
+        let gains = Gains { kd: 0.0, ..GAINS };
+
+        // A long time well below target, e.g. warming up from cold, saturates without integrating
+        for _ in 0..20 {
+            let output = pid.update(&gains, 70.0, 60.0, minutes(10));
+            assert_eq!(output.duty, 1.0);
+            assert_eq!(output.i, 0.0);
+        }
+
+        // Close to the target the integral builds from nothing, rather than a backlog from the
+        // warm up holding the relay on past the target
+        let near = pid.update(&gains, 70.0, 69.5, minutes(10));
+        assert!((near.i - 0.5).abs() < 1e-6, "{:?}", near);
+        let at_target = pid.update(&gains, 70.0, 70.0, minutes(10));
+        assert!((at_target.duty - 0.5).abs() < 1e-6, "{:?}", at_target);
+
+        // And comes straight back down once the room overshoots
+        let over = pid.update(&gains, 70.0, 70.5, minutes(10));
+        assert!(over.i < at_target.i);
+        assert_eq!(over.duty, 0.0);
+    }
+
+    #[test]
+    fn duty_cycle_respects_minimum_times() {
+        let config = ControlConfig::default();
+        let start = Instant::now();
+        let mut cycle = DutyCycle::default();
+
+        assert!(cycle.due(&config, start));
+        assert_eq!(cycle.start(&config, 0.5, start), minutes(5));
+        assert!(cycle.relay_on(start));
+        assert!(cycle.relay_on(start + minutes(4)));
+        assert!(!cycle.relay_on(start + minutes(5)));
+        assert_eq!(
+            cycle.next_switch(&config, start + minutes(1)),
+            Some(start + minutes(5))
+        );
+        assert_eq!(
+            cycle.next_switch(&config, start + minutes(6)),
+            Some(start + minutes(10))
+        );
+        assert!(!cycle.due(&config, start + minutes(9)));
+        assert!(cycle.due(&config, start + minutes(10)));
+        assert_eq!(cycle.elapsed(start + minutes(10)), minutes(10));
+
+        // A minute on is too short, so it stays off
+        assert_eq!(cycle.start(&config, 0.1, start), minutes(0));
+        assert!(!cycle.relay_on(start));
+
+        // A minute off is too short, so it stays on for the whole cycle
+        assert_eq!(cycle.start(&config, 0.9, start), minutes(10));
+        assert!(cycle.relay_on(start + minutes(9)));
+    }
+}
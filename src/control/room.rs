@@ -0,0 +1,68 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Simulated room for testing control end to end.
+///
+/// The boiler takes a couple of minutes to get hot water to the radiators, which then take a
+/// while to warm up and keep giving out heat for a while after they're off. Heat leaks out in
+/// proportion to the difference from outside.
+#[derive(Debug)]
+pub(crate) struct Room {
+    pub(crate) temperature: f32,
+    pub(crate) outside: f32,
+    /// Fahrenheit per minute with the heating fully on
+    pub(crate) heating_rate: f32,
+    /// Fraction of the difference from outside lost per minute
+    loss_rate: f32,
+    /// Relay states on their way to the radiators, one per step
+    pipeline: VecDeque<bool>,
+    /// How warm the radiators are, from 0 when cold to 1 when fully heated
+    radiators: f32,
+    /// Time constant of the radiators warming up and cooling down, in minutes
+    radiator_minutes: f32,
+    step: Duration,
+}
+
+impl Room {
+    /// A bedroom at 64F, 40F outside, with radiators that take around 12 minutes to warm up or
+    /// cool down.
+    pub(crate) fn bedroom() -> Self {
+        let step = Duration::from_secs(30);
+        let lag = Duration::from_secs(2 * 60);
+
+        Self {
+            temperature: 64.0,
+            outside: 40.0,
+            heating_rate: 0.4,
+            loss_rate: 1.0 / 360.0,
+            pipeline: vec![false; (lag.as_secs() / step.as_secs()) as usize].into(),
+            radiators: 0.0,
+            radiator_minutes: 12.0,
+            step,
+        }
+    }
+
+    pub(crate) fn step(&self) -> Duration {
+        self.step
+    }
+
+    /// Advance one step with the relay `on`.
+    pub(crate) fn advance(&mut self, on: bool) {
+        self.pipeline.push_back(on);
+        let heating = self.pipeline.pop_front().unwrap_or_default();
+
+        let minutes = self.step.as_secs_f32() / 60.0;
+        let supply = if heating { 1.0 } else { 0.0 };
+        self.radiators += (supply - self.radiators) * minutes / self.radiator_minutes;
+
+        let heat = self.heating_rate * self.radiators;
+        let loss = self.loss_rate * (self.temperature - self.outside);
+        self.temperature += (heat - loss) * minutes;
+    }
+
+    /// What the sensor reports, which only resolves tenths of a degree Celsius.
+    pub(crate) fn reading(&self) -> f32 {
+        let celsius = (self.temperature - 32.0) / 1.8;
+        (celsius * 10.0).round() / 10.0 * 1.8 + 32.0
+    }
+}
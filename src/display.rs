@@ -1,144 +1,739 @@
-use crate::Status;
-use pwr_hd44780::Hd44780;
-use std::sync::mpsc::{channel, Receiver, Sender};
+use crate::error::{Error, Result};
+use crate::settings::{Settings, Units};
+use crate::{Mode, Status};
+use chrono::{DateTime, Local};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
 use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::Sender as ControlSender;
+use tracing::{error, info, info_span, warn};
 
+mod backlight;
 mod font;
+mod frame;
+mod icons;
+mod lcd;
+mod memory;
+mod oled;
+
+use backlight::{Backlight, Wake};
+use frame::BufferedLcd;
+use lcd::{CompactLcd, LargeLcd};
+use memory::MemoryLcd;
+use oled::Oled;
+
+/// How long the target is shown or hidden for while it's being edited.
+const FLASH_INTERVAL: Duration = Duration::from_millis(500);
+/// How often to check whether the night window has started or ended.
+const NIGHT_WINDOW_CHECK: Duration = Duration::from_secs(60);
+/// Wait before setting the panel up again after an error, doubling for each failed attempt.
+const RECONNECT_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_MAX: Duration = Duration::from_secs(60);
 
 #[derive(Debug, Clone)]
 pub(crate) struct Display {
     events: Sender<Event>,
+    columns: usize,
 }
 
 enum Event {
-    StatusUpdate(Status),
+    /// Boxed as it's much bigger than the rest
+    StatusUpdate(Box<Status>),
+    Wake(Wake),
     SetBacklight(bool),
+    EditTarget(Option<f32>),
+    ShowMenu(Option<Vec<String>>),
 }
 
 impl Display {
-    pub(crate) fn new(device: &'static str, bus: u16) -> Result<Self, Box<dyn std::error::Error>> {
+    /// A 20x4 character LCD behind an I2C backpack.
+    ///
+    /// Backlight changes and the display's health are reported back on `control_tx`.
+    pub(crate) fn new(
+        device: &'static str,
+        bus: u16,
+        control_tx: ControlSender<crate::Event>,
+    ) -> Self {
+        Self::spawn(20, control_tx, move || {
+            let lcd_bus = pwr_hd44780::I2CBus::new(device, bus)?;
+            let lcd = pwr_hd44780::DirectLcd::new(Box::new(lcd_bus), 20, 4)?;
+            LargeLcd::new(BufferedLcd::new(lcd))
+        })
+    }
+
+    /// A 16x2 character LCD behind an I2C backpack.
+    pub(crate) fn compact(
+        device: &'static str,
+        bus: u16,
+        control_tx: ControlSender<crate::Event>,
+    ) -> Self {
+        Self::spawn(16, control_tx, move || {
+            let lcd_bus = pwr_hd44780::I2CBus::new(device, bus)?;
+            let lcd = pwr_hd44780::DirectLcd::new(Box::new(lcd_bus), 16, 2)?;
+            Ok(CompactLcd::new(BufferedLcd::new(lcd)))
+        })
+    }
+
+    /// A 128x64 SSD1306 OLED on I2C bus `bus` at `address`.
+    pub(crate) fn oled(bus: u8, address: u16, control_tx: ControlSender<crate::Event>) -> Self {
+        Self::spawn(oled::COLUMNS, control_tx, move || {
+            let mut i2c = rppal::i2c::I2c::with_bus(bus)?;
+            i2c.set_slave_address(address)?;
+            Oled::new(i2c)
+        })
+    }
+
+    /// Draw the 20x4 layout to the terminal instead of a panel, for running without the hardware.
+    pub(crate) fn terminal(control_tx: ControlSender<crate::Event>) -> Self {
+        Self::spawn(20, control_tx, || LargeLcd::new(MemoryLcd::terminal(20, 4)))
+    }
+
+    /// The panel is driven from its own thread as the drivers can't be sent between threads.
+    ///
+    /// `connect` sets the panel up from scratch, it's called again to recover from errors.
+    fn spawn<S, F>(columns: usize, control_tx: ControlSender<crate::Event>, connect: F) -> Self
+    where
+        S: Screen,
+        F: FnMut() -> Result<S> + Send + 'static,
+    {
         let (tx, rx) = channel();
 
         thread::spawn(move || {
-            let mut lcd = InnerDisplay::new(device, bus).unwrap();
-            lcd.start_loop(rx);
+            let _span = info_span!("display").entered();
+            let mut display = InnerDisplay::new(Box::new(connect), control_tx, Local::now);
+            display.start_loop(rx);
         });
 
-        Ok(Self { events: tx })
+        Self {
+            events: tx,
+            columns,
+        }
     }
 
-    pub(crate) fn update_status(&self, status: &Status) -> Result<(), Box<dyn std::error::Error>> {
-        self.events.send(Event::StatusUpdate(status.clone()))?;
+    /// Characters that fit on a line of menu text.
+    pub(crate) fn columns(&self) -> usize {
+        self.columns
+    }
 
-        Ok(())
+    pub(crate) fn update_status(&self, status: &Status) -> Result<()> {
+        self.send(Event::StatusUpdate(Box::new(status.clone())))
+    }
+
+    /// Light the screen for the backlight timeout after a button press.
+    pub(crate) fn wake(&self) -> Result<()> {
+        self.send(Event::Wake(Wake::Button))
+    }
+
+    /// Light the screen for a new alarm, unless it's the night window.
+    pub(crate) fn alarm(&self) -> Result<()> {
+        self.send(Event::Wake(Wake::Alarm))
+    }
+
+    /// Force the backlight on, or turn it off and go back to automatic.
+    pub(crate) fn set_backlight(&self, on: bool) -> Result<()> {
+        self.send(Event::SetBacklight(on))
+    }
+
+    /// Flash a pending target in place of the current one, `None` goes back to normal.
+    pub(crate) fn edit_target(&self, target: Option<f32>) -> Result<()> {
+        self.send(Event::EditTarget(target))
     }
 
-    pub(crate) fn backlight_on(&self) {
-        self.events.send(Event::SetBacklight(true)).unwrap();
+    /// Replace the status screen with these menu rows, `None` goes back to the status screen.
+    pub(crate) fn show_menu(&self, rows: Option<Vec<String>>) -> Result<()> {
+        self.send(Event::ShowMenu(rows))
     }
 
-    pub(crate) fn backlight_off(&self) {
-        self.events.send(Event::SetBacklight(false)).unwrap();
+    fn send(&self, event: Event) -> Result<()> {
+        self.events
+            .send(event)
+            .map_err(|_| Error::ChannelClosed("display events"))
     }
 }
 
-struct InnerDisplay<T>
+/// What the status screen shows, already converted to the display units.
+struct StatusView {
+    /// `None` without a good reading
+    temperature: Option<f32>,
+    /// `None` while a pending target is flashed off
+    target: Option<f32>,
+    units: Units,
+    humidity: f32,
+    time: DateTime<Local>,
+    running: bool,
+    held: bool,
+    /// Following a schedule with entries, rather than held or switched off
+    scheduled: bool,
+    /// Started heating early to reach the next scheduled target on time
+    preheating: bool,
+    /// `None` when MQTT isn't set up
+    mqtt_connected: Option<bool>,
+    alarm: bool,
+}
+
+/// A layout for one kind of panel.
+trait Screen {
+    fn draw_status(&mut self, view: &StatusView) -> Result<()>;
+    /// Redraw just the target, for flashing it while it's edited.
+    fn draw_target(&mut self, view: &StatusView) -> Result<()>;
+    /// Draw menu rows over a cleared screen, leaving off any that don't fit.
+    fn draw_menu(&mut self, rows: &[String]) -> Result<()>;
+    fn clear(&mut self) -> Result<()>;
+    fn set_backlight(&mut self, on: bool) -> Result<()>;
+    /// Called once all updates for an event have been drawn.
+    fn present(&mut self) -> Result<()>;
+}
+
+struct InnerDisplay<S>
 where
-    T: Hd44780,
+    S: Screen,
 {
-    lcd: T,
+    /// `None` until connected, and after an error until the next retry
+    screen: Option<S>,
+    connect: Box<dyn FnMut() -> Result<S> + Send>,
+    reconnect_at: Option<Instant>,
+    reconnect_delay: Duration,
+    healthy: bool,
+    /// Last status shown, to redraw after the menu closes
+    status: Option<Status>,
+    editing: Option<f32>,
+    flash_visible: bool,
+    next_flash: Option<Instant>,
+    /// Rows of the open menu, to redraw after reconnecting
+    menu: Option<Vec<String>>,
+    backlight: Backlight,
+    backlight_on: bool,
+    control_tx: ControlSender<crate::Event>,
+    /// Wall clock time, fixed in tests
+    clock: fn() -> DateTime<Local>,
 }
 
-impl InnerDisplay<pwr_hd44780::DirectLcd> {
-    pub(crate) fn new(device: &str, bus: u16) -> Result<Self, Box<dyn std::error::Error>> {
-        let lcd_bus = pwr_hd44780::I2CBus::new(device, bus)?;
-        let mut lcd = pwr_hd44780::DirectLcd::new(Box::new(lcd_bus), 20, 4)?;
-        lcd.set_backlight(false)?;
-        lcd.clear()?;
+impl<S> InnerDisplay<S>
+where
+    S: Screen,
+{
+    /// Starts disconnected, `reconnect` sets up the panel.
+    fn new(
+        connect: Box<dyn FnMut() -> Result<S> + Send>,
+        control_tx: ControlSender<crate::Event>,
+        clock: fn() -> DateTime<Local>,
+    ) -> Self {
+        Self {
+            screen: None,
+            connect,
+            reconnect_at: Some(Instant::now()),
+            reconnect_delay: RECONNECT_MIN,
+            healthy: true,
+            status: None,
+            editing: None,
+            flash_visible: true,
+            next_flash: None,
+            menu: None,
+            backlight: Backlight::new(Settings::default().backlight_timeout()),
+            backlight_on: false,
+            control_tx,
+            clock,
+        }
+    }
 
-        font::setup(&mut lcd)?;
+    fn update_status(&mut self, status: Status) -> Result<()> {
+        let settings = status.settings;
+        self.backlight
+            .configure(settings.backlight_timeout(), settings.night_window);
 
-        Ok(Self { lcd })
+        self.status = Some(status);
+        self.print_status()
     }
-}
 
-impl<T> InnerDisplay<T>
-where
-    T: Hd44780,
-{
-    fn update_status(&mut self, status: &Status) -> Result<(), Box<dyn std::error::Error>> {
-        let temp = status.temperature;
-        let [first, middle, last] = split_digits(temp);
-        font::print_big_char(&mut self.lcd, first, 0, 0)?;
-        font::print_big_char(&mut self.lcd, middle, 5, 0)?;
-        // Use bottom fill char to approximate a dot
-        self.lcd.print_char_at(3, 9, 5)?;
-        font::print_big_char(&mut self.lcd, last, 10, 0)?;
-
-        // self.lcd.print_at(0, 10, "F")?;
-
-        self.lcd
-            .print_at(0, 15, format!("{:.1}F", status.target_temperature))?;
-        self.lcd
-            .print_at(1, 15, format!("{:.1}%", status.humidity))?;
-        self.lcd
-            .print_at(2, 15, chrono::Local::now().format("%l:%M").to_string())?;
-
-        let run_status = if status.running { "On" } else { "Off" };
-        self.lcd.print_at(3, 17, format!("{:>3}", run_status))?;
+    /// `None` while the menu is covering the status screen.
+    fn status_view(&self) -> Option<StatusView> {
+        let status = match &self.status {
+            Some(status) if self.menu.is_none() => status,
+            _ => return None,
+        };
+        let units = status.settings.units;
+        let target = match self.editing {
+            Some(_) if !self.flash_visible => None,
+            Some(pending) => Some(pending),
+            None => Some(status.target_temperature),
+        };
 
-        Ok(())
+        let time = (self.clock)();
+
+        Some(StatusView {
+            temperature: if status.sensor_fault {
+                None
+            } else {
+                Some(units.convert(status.temperature))
+            },
+            target: target.map(|target| units.convert(target)),
+            units,
+            humidity: status.humidity,
+            time,
+            running: status.running,
+            held: status.hold.is_some(),
+            scheduled: status.mode == Mode::Heat
+                && status.hold.is_none()
+                && !status.schedule.entries().is_empty(),
+            preheating: matches!(status.optimal_start, Some(start) if start <= time),
+            mqtt_connected: status.mqtt_connected,
+            alarm: !status.alarms.is_empty(),
+        })
+    }
+
+    fn print_status(&mut self) -> Result<()> {
+        match (self.status_view(), &mut self.screen) {
+            (Some(view), Some(screen)) => screen.draw_status(&view),
+            _ => Ok(()),
+        }
+    }
+
+    fn print_target(&mut self) -> Result<()> {
+        match (self.status_view(), &mut self.screen) {
+            (Some(view), Some(screen)) => screen.draw_target(&view),
+            _ => Ok(()),
+        }
     }
 
     fn start_loop(&mut self, events: Receiver<Event>) {
         loop {
-            match events.recv() {
-                Ok(event) => self.handle_event(event).unwrap(),
+            self.reconnect(Instant::now());
+
+            // Only wake up periodically while there's something flashing or a timer to check
+            let event = match self.next_wakeup() {
+                Some(wakeup) => {
+                    let timeout = wakeup.saturating_duration_since(Instant::now());
+                    match events.recv_timeout(timeout) {
+                        Ok(event) => Ok(Some(event)),
+                        Err(RecvTimeoutError::Timeout) => Ok(None),
+                        Err(RecvTimeoutError::Disconnected) => Err(RecvTimeoutError::Disconnected),
+                    }
+                }
+                None => events
+                    .recv()
+                    .map(Some)
+                    .map_err(|_| RecvTimeoutError::Disconnected),
+            };
+
+            let result = match event {
+                Ok(Some(event)) => {
+                    // Catch up on anything else that's queued before drawing
+                    let mut batch = vec![event];
+                    batch.extend(events.try_iter());
+                    coalesce(batch)
+                        .into_iter()
+                        .try_for_each(|event| self.handle_event(event))
+                }
+                Ok(None) => self.flash(),
                 Err(e) => {
-                    eprintln!("Error {} in receiving button event", e);
+                    error!(error = %e, "Error in receiving display event");
                     break;
                 }
             };
+
+            let result = result
+                .and_then(|_| self.update_backlight())
+                .and_then(|_| self.present());
+            if let Err(e) = result {
+                self.disconnect(e, Instant::now());
+            }
+        }
+    }
+
+    /// Drop the panel after an error, it's set up again from scratch once the backoff has passed.
+    fn disconnect(&mut self, error: Error, now: Instant) {
+        error!(error = %error, retry_in = ?self.reconnect_delay, "Display error");
+
+        self.screen = None;
+        self.reconnect_at = Some(now + self.reconnect_delay);
+        self.reconnect_delay = (self.reconnect_delay * 2).min(RECONNECT_MAX);
+        self.report_health(false);
+    }
+
+    /// Set up the panel if a retry is due, redrawing everything on it.
+    fn reconnect(&mut self, now: Instant) {
+        match self.reconnect_at {
+            Some(at) if now >= at => {}
+            _ => return,
+        }
+
+        let result = (self.connect)().and_then(|screen| {
+            self.screen = Some(screen);
+            self.redraw()
+        });
+
+        match result {
+            Ok(()) => {
+                if !self.healthy {
+                    info!("Display recovered");
+                }
+                self.reconnect_at = None;
+                self.reconnect_delay = RECONNECT_MIN;
+                self.report_health(true);
+            }
+            Err(e) => self.disconnect(e, now),
+        }
+    }
+
+    fn redraw(&mut self) -> Result<()> {
+        if let Some(screen) = &mut self.screen {
+            screen.set_backlight(self.backlight_on)?;
+            screen.clear()?;
+
+            match &self.menu {
+                Some(rows) => screen.draw_menu(rows)?,
+                None => self.print_status()?,
+            }
+        }
+
+        self.present()
+    }
+
+    fn present(&mut self) -> Result<()> {
+        match &mut self.screen {
+            Some(screen) => screen.present(),
+            None => Ok(()),
+        }
+    }
+
+    /// Let the controller know when the display stops or starts working.
+    fn report_health(&mut self, healthy: bool) {
+        if healthy == self.healthy {
+            return;
+        }
+        self.healthy = healthy;
+
+        if let Err(e) = self
+            .control_tx
+            .try_send(crate::Event::DisplayHealth(healthy))
+        {
+            warn!(error = %e, "Couldn't report display health");
+        }
+    }
+
+    fn next_wakeup(&self) -> Option<Instant> {
+        let now = Instant::now();
+        let night_check = if self.backlight.has_night_window() {
+            Some(now + NIGHT_WINDOW_CHECK)
+        } else {
+            None
+        };
+
+        self.next_flash
+            .into_iter()
+            .chain(self.reconnect_at)
+            .chain(self.backlight.next_timeout(now))
+            .chain(night_check)
+            .min()
+    }
+
+    fn flash(&mut self) -> Result<()> {
+        match self.next_flash {
+            Some(next_flash) if Instant::now() >= next_flash => {
+                self.next_flash = Some(next_flash + FLASH_INTERVAL);
+                self.flash_visible = !self.flash_visible;
+                self.print_target()
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Switch the backlight if it's due a change, letting the controller know.
+    fn update_backlight(&mut self) -> Result<()> {
+        let on = self.backlight.is_on(Instant::now(), (self.clock)().time());
+        if on == self.backlight_on {
+            return Ok(());
+        }
+
+        self.backlight_on = on;
+        if let Some(screen) = &mut self.screen {
+            screen.set_backlight(on)?;
+        }
+
+        if let Err(e) = self.control_tx.try_send(crate::Event::BacklightChanged(on)) {
+            warn!(error = %e, "Couldn't report backlight change");
         }
+
+        Ok(())
     }
 
-    fn handle_event(&mut self, event: Event) -> Result<(), Box<dyn std::error::Error>> {
+    fn handle_event(&mut self, event: Event) -> Result<()> {
         match event {
-            Event::SetBacklight(true) => self.lcd.set_backlight(true)?,
-            Event::SetBacklight(false) => self.lcd.set_backlight(false)?,
-            Event::StatusUpdate(status) => self.update_status(&status)?,
+            Event::Wake(reason) => self.backlight.wake(reason, Instant::now()),
+            Event::SetBacklight(on) => self.backlight.force(on),
+            Event::StatusUpdate(status) => self.update_status(*status)?,
+            Event::EditTarget(target) => {
+                self.editing = target;
+                self.flash_visible = true;
+                self.next_flash = target.map(|_| Instant::now() + FLASH_INTERVAL);
+                self.print_target()?;
+            }
+            Event::ShowMenu(Some(rows)) => {
+                let opening = self.menu.is_none();
+                if let Some(screen) = &mut self.screen {
+                    if opening {
+                        screen.clear()?;
+                    }
+                    screen.draw_menu(&rows)?;
+                }
+                self.menu = Some(rows);
+            }
+            Event::ShowMenu(None) => {
+                self.menu = None;
+                if let Some(screen) = &mut self.screen {
+                    screen.clear()?;
+                }
+                self.print_status()?;
+            }
         }
 
         Ok(())
     }
 }
 
-fn split_digits(number: f32) -> [usize; 3] {
-    assert!(number > 0.0 && number < 100.0);
+/// Drop all but the last status update, the earlier ones would be drawn over straight away.
+fn coalesce(events: Vec<Event>) -> Vec<Event> {
+    let is_status = |event: &Event| matches!(event, Event::StatusUpdate(_));
+    let last_status = events.iter().rposition(is_status);
 
-    let digits = (number * 10.0).round() as usize;
-    [digits / 100, (digits / 10) % 10, (digits % 10)]
+    events
+        .into_iter()
+        .enumerate()
+        .filter(|(i, event)| !is_status(event) || Some(*i) == last_status)
+        .map(|(_, event)| event)
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::schedule::Hold;
+    use crate::settings::Units;
+    use chrono::TimeZone;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn clock() -> DateTime<Local> {
+        Local.ymd(2020, 1, 2).and_hms(9, 41, 0)
+    }
+
+    fn connected(control_tx: ControlSender<crate::Event>) -> InnerDisplay<LargeLcd<MemoryLcd>> {
+        let connect = || LargeLcd::new(MemoryLcd::new(20, 4));
+        let mut display = InnerDisplay::new(Box::new(connect), control_tx, clock);
+
+        display.reconnect(Instant::now());
+        display
+    }
+
+    fn rows(display: &InnerDisplay<LargeLcd<MemoryLcd>>) -> Vec<String> {
+        display.screen.as_ref().unwrap().lcd.rows()
+    }
+
+    fn screen(status: Status) -> Vec<String> {
+        let (control_tx, _control_rx) = tokio::sync::mpsc::channel(1);
+        let mut display = connected(control_tx);
+
+        display
+            .handle_event(Event::StatusUpdate(Box::new(status)))
+            .unwrap();
+
+        rows(&display)
+    }
+
+    fn status(temperature: f32, humidity: f32, target_temperature: f32) -> Status {
+        let mut status = Status::new(target_temperature, false);
+        status.temperature = temperature;
+        status.humidity = humidity;
+        status.sensor_fault = false;
+        status
+    }
 
     #[test]
-    fn split_digits_test() {
-        let [first, middle, last] = split_digits(12.3456);
+    fn normal_reading() {
+        let mut status = status(70.26, 40.0, 70.0);
+        status.running = true;
+        status.hold = Some(Hold::Indefinite);
+        status.mqtt_connected = Some(true);
 
-        assert_eq!(first, 1);
-        assert_eq!(middle, 2);
-        assert_eq!(last, 3);
+        assert_eq!(
+            screen(status),
+            vec![
+                "█▀▀█ █▀▀█ █▀▀█ 70.0F",
+                "  ▄█ █  █   ▄█ 40.0%",
+                " █▀  █  █   ▀█  9:41",
+                " █   █▄▄█▄█▄▄█  HM *",
+            ]
+        );
     }
 
     #[test]
-    fn split_digits_test2() {
-        let [first, middle, last] = split_digits(70.26);
+    fn missing_reading_and_wide_values() {
+        let mut status = status(0.0, 100.0, 100.0);
+        status.sensor_fault = true;
+
+        assert_eq!(
+            screen(status),
+            vec![
+                "                100F",
+                "   ▄▄▄ ▄▄▄ ▄▄▄  100%",
+                "   ▀▀▀ ▀▀▀ ▀▀▀  9:41",
+                "          ▄       ? ",
+            ]
+        );
+    }
+
+    #[test]
+    fn negative_celsius() {
+        let mut status = status(23.0, 35.0, 68.0);
+        status.settings.units = Units::Celsius;
+
+        assert_eq!(
+            screen(status),
+            vec![
+                "     █▀▀▀ █▀▀█ 20.0C",
+                " ▄▄▄ ▀▀▀█ █  █ 35.0%",
+                " ▀▀▀    █ █  █  9:41",
+                "     █▄▄█▄█▄▄█      ",
+            ]
+        );
+    }
+
+    #[test]
+    fn menu_replaces_status_until_closed() {
+        let (control_tx, _control_rx) = tokio::sync::mpsc::channel(1);
+        let mut display = connected(control_tx);
+        let menu: Vec<String> = vec!["Mode".into(), "Heat".into(), "".into(), "".into()];
+
+        display
+            .handle_event(Event::StatusUpdate(Box::new(status(70.0, 40.0, 70.0))))
+            .unwrap();
+        display.handle_event(Event::ShowMenu(Some(menu))).unwrap();
+        display
+            .handle_event(Event::StatusUpdate(Box::new(status(71.0, 40.0, 70.0))))
+            .unwrap();
+
+        assert_eq!(rows(&display)[0], format!("{:<20}", "Mode"));
+        assert_eq!(rows(&display)[1], format!("{:<20}", "Heat"));
+
+        display.handle_event(Event::ShowMenu(None)).unwrap();
+
+        assert_eq!(rows(&display)[0], "█▀▀█  ██  █▀▀█ 70.0F");
+    }
+
+    #[test]
+    fn only_the_latest_status_is_drawn() {
+        let events = coalesce(vec![
+            Event::StatusUpdate(Box::new(status(70.0, 40.0, 70.0))),
+            Event::EditTarget(Some(71.0)),
+            Event::StatusUpdate(Box::new(status(70.5, 40.0, 70.0))),
+            Event::SetBacklight(true),
+        ]);
+
+        assert_eq!(events.len(), 3);
+        assert!(matches!(&events[0], Event::EditTarget(Some(_))));
+        assert!(matches!(&events[1], Event::StatusUpdate(status) if status.temperature == 70.5));
+        assert!(matches!(&events[2], Event::SetBacklight(true)));
+    }
+
+    /// Fails everything while `unplugged` is set, like a panel with a loose cable.
+    struct FlakyScreen {
+        unplugged: Arc<AtomicBool>,
+        statuses_drawn: Arc<AtomicUsize>,
+    }
+
+    impl FlakyScreen {
+        fn check(&self) -> Result<()> {
+            if self.unplugged.load(Ordering::SeqCst) {
+                Err(Error::Display("I2C write failed".to_string()))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    impl Screen for FlakyScreen {
+        fn draw_status(&mut self, _view: &StatusView) -> Result<()> {
+            self.check()?;
+            self.statuses_drawn.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn draw_target(&mut self, _view: &StatusView) -> Result<()> {
+            self.check()
+        }
+
+        fn draw_menu(&mut self, _rows: &[String]) -> Result<()> {
+            self.check()
+        }
+
+        fn clear(&mut self) -> Result<()> {
+            self.check()
+        }
+
+        fn set_backlight(&mut self, _on: bool) -> Result<()> {
+            self.check()
+        }
+
+        fn present(&mut self) -> Result<()> {
+            self.check()
+        }
+    }
+
+    #[test]
+    fn recovers_after_errors_with_backoff() {
+        let unplugged = Arc::new(AtomicBool::new(false));
+        let statuses_drawn = Arc::new(AtomicUsize::new(0));
+        let connect = {
+            let unplugged = unplugged.clone();
+            let statuses_drawn = statuses_drawn.clone();
+            move || {
+                let screen = FlakyScreen {
+                    unplugged: unplugged.clone(),
+                    statuses_drawn: statuses_drawn.clone(),
+                };
+                screen.check().map(|_| screen)
+            }
+        };
+        let (control_tx, mut control_rx) = tokio::sync::mpsc::channel(10);
+        let mut display = InnerDisplay::new(Box::new(connect), control_tx, clock);
+        let start = Instant::now();
+
+        display.reconnect(start);
+        assert!(display.screen.is_some());
+
+        unplugged.store(true, Ordering::SeqCst);
+        let error = display
+            .handle_event(Event::StatusUpdate(Box::new(status(70.0, 40.0, 70.0))))
+            .unwrap_err();
+        display.disconnect(error, start);
+        assert!(display.screen.is_none());
+        assert!(matches!(
+            control_rx.try_recv(),
+            Ok(crate::Event::DisplayHealth(false))
+        ));
+
+        // Updates while it's down are kept for when it's back
+        display
+            .handle_event(Event::StatusUpdate(Box::new(status(71.0, 40.0, 70.0))))
+            .unwrap();
+
+        // Still unplugged, so the next wait is longer
+        let retry = start + RECONNECT_MIN;
+        display.reconnect(retry);
+        assert!(display.screen.is_none());
+        assert_eq!(display.reconnect_at, Some(retry + RECONNECT_MIN * 2));
+
+        unplugged.store(false, Ordering::SeqCst);
+        display.reconnect(retry + RECONNECT_MIN);
+        assert!(display.screen.is_none());
 
-        assert_eq!(first, 7);
-        assert_eq!(middle, 0);
-        assert_eq!(last, 3);
+        display.reconnect(retry + RECONNECT_MIN * 2);
+        assert!(display.screen.is_some());
+        assert_eq!(display.reconnect_delay, RECONNECT_MIN);
+        assert_eq!(statuses_drawn.load(Ordering::SeqCst), 1);
+        assert!(matches!(
+            control_rx.try_recv(),
+            Ok(crate::Event::DisplayHealth(true))
+        ));
     }
 }
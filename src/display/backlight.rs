@@ -0,0 +1,151 @@
+use crate::settings::NightWindow;
+use chrono::NaiveTime;
+use std::time::{Duration, Instant};
+
+/// How long a press lights the screen at night when it's otherwise set to always on.
+const NIGHT_WAKE: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Wake {
+    Button,
+    Alarm,
+}
+
+/// Decides whether the backlight should be on.
+///
+/// It stays on for the timeout after a button press or a new alarm. During the night window only
+/// button presses light it. Turning it on over MQTT overrides all of that until it's turned off.
+#[derive(Debug)]
+pub(crate) struct Backlight {
+    /// `None` keeps it on, outside of the night window
+    timeout: Option<Duration>,
+    night_window: Option<NightWindow>,
+    awake_until: Option<Instant>,
+    button_until: Option<Instant>,
+    forced_on: bool,
+}
+
+impl Backlight {
+    pub(crate) fn new(timeout: Option<Duration>) -> Self {
+        Self {
+            timeout,
+            night_window: None,
+            awake_until: None,
+            button_until: None,
+            forced_on: false,
+        }
+    }
+
+    pub(crate) fn configure(
+        &mut self,
+        timeout: Option<Duration>,
+        night_window: Option<NightWindow>,
+    ) {
+        self.timeout = timeout;
+        self.night_window = night_window;
+    }
+
+    pub(crate) fn wake(&mut self, reason: Wake, now: Instant) {
+        let until = now + self.timeout.unwrap_or(NIGHT_WAKE);
+
+        self.awake_until = Some(until);
+        if reason == Wake::Button {
+            self.button_until = Some(until);
+        }
+    }
+
+    /// Turning it off goes back to automatic, so the next press lights it again.
+    pub(crate) fn force(&mut self, on: bool) {
+        self.forced_on = on;
+
+        if !on {
+            self.awake_until = None;
+            self.button_until = None;
+        }
+    }
+
+    pub(crate) fn is_on(&self, now: Instant, time: NaiveTime) -> bool {
+        let still_before = |until: Option<Instant>| matches!(until, Some(until) if now < until);
+        let night = matches!(self.night_window, Some(window) if window.contains(time));
+
+        if self.forced_on {
+            true
+        } else if night {
+            still_before(self.button_until)
+        } else {
+            self.timeout.is_none() || still_before(self.awake_until)
+        }
+    }
+
+    /// When a timeout next runs out, if one is pending.
+    pub(crate) fn next_timeout(&self, now: Instant) -> Option<Instant> {
+        self.awake_until
+            .into_iter()
+            .chain(self.button_until)
+            .filter(|&until| until > now)
+            .min()
+    }
+
+    pub(crate) fn has_night_window(&self) -> bool {
+        self.night_window.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(30);
+
+    fn noon() -> NaiveTime {
+        NaiveTime::from_hms(12, 0, 0)
+    }
+
+    fn midnight() -> NaiveTime {
+        NaiveTime::from_hms(0, 0, 0)
+    }
+
+    #[test]
+    fn turns_off_after_timeout() {
+        let mut backlight = Backlight::new(Some(TIMEOUT));
+        let now = Instant::now();
+
+        assert!(!backlight.is_on(now, noon()));
+
+        backlight.wake(Wake::Alarm, now);
+        assert!(backlight.is_on(now, noon()));
+        assert_eq!(backlight.next_timeout(now), Some(now + TIMEOUT));
+        assert!(!backlight.is_on(now + TIMEOUT, noon()));
+    }
+
+    #[test]
+    fn night_window_only_lights_for_buttons() {
+        let mut backlight = Backlight::new(None);
+        backlight.configure(None, Some("22:00-07:00".parse().unwrap()));
+        let now = Instant::now();
+
+        assert!(backlight.is_on(now, noon()));
+        assert!(!backlight.is_on(now, midnight()));
+
+        backlight.wake(Wake::Alarm, now);
+        assert!(!backlight.is_on(now, midnight()));
+
+        backlight.wake(Wake::Button, now);
+        assert!(backlight.is_on(now, midnight()));
+        assert!(!backlight.is_on(now + NIGHT_WAKE, midnight()));
+    }
+
+    #[test]
+    fn forced_on_until_turned_off() {
+        let mut backlight = Backlight::new(Some(TIMEOUT));
+        backlight.configure(Some(TIMEOUT), Some("22:00-07:00".parse().unwrap()));
+        let now = Instant::now();
+
+        backlight.force(true);
+        assert!(backlight.is_on(now + TIMEOUT, midnight()));
+
+        backlight.wake(Wake::Button, now);
+        backlight.force(false);
+        assert!(!backlight.is_on(now, noon()));
+    }
+}
@@ -2,40 +2,169 @@
 // https://github.com/gcassarino/BigFont/blob/ee4c39133df1eeed914733f8cb8170e2b440cdae/src/BigFont.h
 use pwr_hd44780::Hd44780;
 
+/// CGRAM slots the big font leaves free, for icons.
+pub(crate) const FREE_SLOTS: [u8; 2] = [0, 7];
+
 pub(crate) fn setup(lcd: &mut impl Hd44780) -> Result<(), Box<dyn std::error::Error>> {
-    lcd.create_char(0, CUSTCHAR3[0])?;
-    lcd.create_char(1, CUSTCHAR3[1])?;
-    lcd.create_char(2, CUSTCHAR3[2])?;
-    lcd.create_char(3, CUSTCHAR3[3])?;
-    lcd.create_char(4, CUSTCHAR3[4])?;
-    lcd.create_char(5, CUSTCHAR3[5])?;
-    lcd.create_char(6, CUSTCHAR3[6])?;
-    lcd.create_char(7, CUSTCHAR3[7])?;
+    for slot in 1..=6 {
+        lcd.create_char(slot, CUSTCHAR3[slot as usize])?;
+    }
 
     Ok(())
 }
 
-pub(crate) fn print_big_char(
+/// Columns taken by a big number, laid out right-aligned so the decimal point stays put.
+pub(crate) const BIG_NUMBER_WIDTH: usize = 14;
+
+/// One big character, four rows tall.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Glyph {
+    Digit(u8),
+    Minus,
+    Dot,
+}
+
+impl Glyph {
+    fn rows(self) -> &'static [[u8; 4]; 4] {
+        match self {
+            Glyph::Digit(digit) => &BIGNUMS[digit as usize],
+            Glyph::Minus => &MINUS,
+            Glyph::Dot => &DOT,
+        }
+    }
+
+    fn width(self) -> usize {
+        match self {
+            Glyph::Digit(_) => 4,
+            Glyph::Minus => 3,
+            Glyph::Dot => 1,
+        }
+    }
+}
+
+/// Glyphs for a temperature, `None` for no reading.
+///
+/// Tenths are shown while they fit, otherwise the value is rounded to a whole number. Anything
+/// that still doesn't fit in three characters is shown as missing.
+pub(crate) fn big_number(value: Option<f32>) -> Vec<Glyph> {
+    const MISSING: [Glyph; 4] = [Glyph::Minus, Glyph::Minus, Glyph::Dot, Glyph::Minus];
+
+    let value = match value {
+        Some(value) if value.is_finite() => value,
+        _ => return MISSING.to_vec(),
+    };
+    let digit = |n: i32| Glyph::Digit(n as u8);
+    let tenths = (value * 10.0).round() as i32;
+
+    match tenths {
+        // No leading zero under ten
+        0..=99 => vec![digit(tenths / 10), Glyph::Dot, digit(tenths % 10)],
+        100..=999 => vec![
+            digit(tenths / 100),
+            digit(tenths / 10 % 10),
+            Glyph::Dot,
+            digit(tenths % 10),
+        ],
+        -99..=-1 => vec![
+            Glyph::Minus,
+            digit(-tenths / 10),
+            Glyph::Dot,
+            digit(-tenths % 10),
+        ],
+        _ => match value.round() as i32 {
+            whole @ 100..=999 => vec![
+                digit(whole / 100),
+                digit(whole / 10 % 10),
+                digit(whole % 10),
+            ],
+            whole @ -99..=-10 => vec![Glyph::Minus, digit(-whole / 10), digit(-whole % 10)],
+            _ => MISSING.to_vec(),
+        },
+    }
+}
+
+/// Print a big number with its top left corner at `row`/`col`, blanking any unused columns.
+pub(crate) fn print_big_number(
     lcd: &mut impl Hd44780,
-    digit: usize,
+    value: Option<f32>,
     col: usize,
     row: usize,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    // FIXME: This should really return an error
-    if digit > 9 {
-        return Ok(());
+    for (line, cells) in big_number_cells(value).iter().enumerate() {
+        lcd.move_at(row + line, col)?;
+        for &c in cells {
+            lcd.print_char(c)?;
+        }
     }
 
-    for i in 0..4 {
-        lcd.move_at(row + i, col)?;
+    Ok(())
+}
+
+/// Character codes for a big number, right-aligned in `BIG_NUMBER_WIDTH` columns.
+pub(crate) fn big_number_cells(value: Option<f32>) -> [[u8; BIG_NUMBER_WIDTH]; 4] {
+    let glyphs = big_number(value);
+    let positions = glyph_columns(&glyphs);
+    let used = positions
+        .last()
+        .zip(glyphs.last())
+        .map_or(0, |(&start, glyph)| start + glyph.width());
+    let offset = BIG_NUMBER_WIDTH.saturating_sub(used);
+    let mut cells = [[BLANK; BIG_NUMBER_WIDTH]; 4];
 
-        for j in 0..4 {
-            lcd.print_char(BIGNUMS[digit][i][j])?;
+    for (glyph, &start) in glyphs.iter().zip(&positions) {
+        for (line, row) in glyph.rows().iter().enumerate() {
+            let col = offset + start;
+            cells[line][col..col + glyph.width()].copy_from_slice(&row[..glyph.width()]);
         }
     }
 
-    Ok(())
+    cells
+}
+
+/// Pixel rows of a character cell from `big_number_cells`, five pixels wide in the low bits.
+pub(crate) fn cell_pixels(code: u8) -> [u8; 8] {
+    match code {
+        0..=7 => CUSTCHAR3[code as usize],
+        FULL => [0b11111; 8],
+        _ => [0; 8],
+    }
 }
+
+/// Starting column of each glyph, with a gap between them except either side of a dot.
+fn glyph_columns(glyphs: &[Glyph]) -> Vec<usize> {
+    let mut columns = Vec::with_capacity(glyphs.len());
+    let mut col = 0;
+
+    for (i, glyph) in glyphs.iter().enumerate() {
+        if i > 0 && *glyph != Glyph::Dot && glyphs[i - 1] != Glyph::Dot {
+            col += 1;
+        }
+        columns.push(col);
+        col += glyph.width();
+    }
+
+    columns
+}
+
+const BLANK: u8 = 254;
+const FULL: u8 = 255;
+
+/// Built from the top and bottom bars, so it doesn't need a CGRAM slot of its own.
+const MINUS: [[u8; 4]; 4] = [
+    [254, 254, 254, 254],
+    [5, 5, 5, 254],
+    [2, 2, 2, 254],
+    [254, 254, 254, 254],
+];
+
+/// Bottom fill char to approximate a dot.
+const DOT: [[u8; 4]; 4] = [
+    [254, 254, 254, 254],
+    [254, 254, 254, 254],
+    [254, 254, 254, 254],
+    [5, 254, 254, 254],
+];
+
 const BIGNUMS: [[[u8; 4]; 4]; 10] = [
     [
         // 0
@@ -111,8 +240,8 @@ const BIGNUMS: [[[u8; 4]; 4]; 10] = [
 
 const CUSTCHAR3: [[u8; 8]; 8] = [
     [
-        // 0 // slash used for number 4
-        0b00001, 0b00011, 0b00111, 0b01111, 0b11111, 0b11110, 0b11100, 0b11000,
+        // 0 (free)
+        0, 0, 0, 0, 0, 0, 0, 0,
     ],
     [
         // 1
@@ -139,7 +268,62 @@ const CUSTCHAR3: [[u8; 8]; 8] = [
         0b11111, 0b11111, 0b11111, 0b11111, 0b11111, 0b11110, 0b11100, 0b11000,
     ],
     [
-        // 7 (alarm symbol)
-        0b00100, 0b01110, 0b01110, 0b01110, 0b11111, 0b00000, 0b00100, 0b00000,
+        // 7 (free)
+        0, 0, 0, 0, 0, 0, 0, 0,
     ],
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Glyph::{Digit, Dot, Minus};
+
+    fn width(glyphs: &[Glyph]) -> usize {
+        let columns = glyph_columns(glyphs);
+        columns[columns.len() - 1] + glyphs[glyphs.len() - 1].width()
+    }
+
+    #[test]
+    fn two_digits_with_tenths() {
+        assert_eq!(
+            big_number(Some(12.3456)),
+            vec![Digit(1), Digit(2), Dot, Digit(3)]
+        );
+        assert_eq!(
+            big_number(Some(70.26)),
+            vec![Digit(7), Digit(0), Dot, Digit(3)]
+        );
+        assert_eq!(glyph_columns(&big_number(Some(70.26))), vec![0, 5, 9, 10]);
+    }
+
+    #[test]
+    fn single_digit_has_no_leading_zero() {
+        assert_eq!(big_number(Some(0.0)), vec![Digit(0), Dot, Digit(0)]);
+        assert_eq!(big_number(Some(5.32)), vec![Digit(5), Dot, Digit(3)]);
+    }
+
+    #[test]
+    fn three_digits_drop_tenths() {
+        let glyphs = big_number(Some(99.96));
+
+        assert_eq!(glyphs, vec![Digit(1), Digit(0), Digit(0)]);
+        assert_eq!(width(&glyphs), BIG_NUMBER_WIDTH);
+    }
+
+    #[test]
+    fn negative_values() {
+        assert_eq!(big_number(Some(-5.3)), vec![Minus, Digit(5), Dot, Digit(3)]);
+        assert_eq!(big_number(Some(-12.4)), vec![Minus, Digit(1), Digit(2)]);
+        assert!(width(&big_number(Some(-12.4))) <= BIG_NUMBER_WIDTH);
+    }
+
+    #[test]
+    fn missing_or_out_of_range_is_a_placeholder() {
+        let missing = vec![Minus, Minus, Dot, Minus];
+
+        assert_eq!(big_number(None), missing);
+        assert_eq!(big_number(Some(f32::NAN)), missing);
+        assert_eq!(big_number(Some(1000.0)), missing);
+        assert_eq!(big_number(Some(-100.0)), missing);
+    }
+}
@@ -0,0 +1,187 @@
+use super::lcd::Panel;
+use super::memory::MemoryLcd;
+use pwr_hd44780::{Hd44780, UnitResult};
+
+/// Draws into memory and only sends the cells that changed to the panel when presented.
+///
+/// Every character written costs a few I2C transfers, so redrawing the whole status screen on
+/// each update is slow enough to flicker. Custom characters and the backlight go straight through.
+pub(super) struct BufferedLcd<T: Panel> {
+    lcd: T,
+    frame: MemoryLcd,
+    /// What's on the panel, `None` where it isn't known
+    shown: Vec<Vec<Option<u8>>>,
+}
+
+impl<T: Panel> BufferedLcd<T> {
+    pub(super) fn new(lcd: T) -> Self {
+        let (width, height) = (lcd.width(), lcd.height());
+
+        Self {
+            lcd,
+            frame: MemoryLcd::new(width, height),
+            shown: vec![vec![None; width]; height],
+        }
+    }
+
+    /// Send the changed cells, moving the cursor only where there's a gap.
+    fn flush(&mut self) -> UnitResult {
+        for (row, shown) in self.shown.iter_mut().enumerate() {
+            let mut cursor = None;
+
+            for (col, shown) in shown.iter_mut().enumerate() {
+                let wanted = self.frame.cell(row, col);
+                if *shown == Some(wanted) {
+                    continue;
+                }
+
+                if cursor != Some(col) {
+                    self.lcd.move_at(row, col)?;
+                }
+                self.lcd.print_char(wanted)?;
+                *shown = Some(wanted);
+                cursor = Some(col + 1);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<T: Panel> Panel for BufferedLcd<T> {
+    fn present(&mut self) -> UnitResult {
+        self.flush()?;
+        self.lcd.present()
+    }
+}
+
+impl<T: Panel> Hd44780 for BufferedLcd<T> {
+    fn clear(&mut self) -> UnitResult {
+        self.frame.clear()
+    }
+
+    fn home(&mut self) -> UnitResult {
+        self.frame.home()
+    }
+
+    fn move_at(&mut self, y: usize, x: usize) -> UnitResult {
+        self.frame.move_at(y, x)
+    }
+
+    fn print_char(&mut self, ch: u8) -> UnitResult {
+        self.frame.print_char(ch)
+    }
+
+    fn set_backlight(&mut self, enabled: bool) -> UnitResult {
+        self.lcd.set_backlight(enabled)
+    }
+
+    fn set_cursor_blinking(&mut self, enabled: bool) -> UnitResult {
+        self.lcd.set_cursor_blinking(enabled)
+    }
+
+    fn set_cursor_visible(&mut self, enabled: bool) -> UnitResult {
+        self.lcd.set_cursor_visible(enabled)
+    }
+
+    fn set_text_visible(&mut self, enabled: bool) -> UnitResult {
+        self.lcd.set_text_visible(enabled)
+    }
+
+    fn create_char(&mut self, idx: u8, lines: [u8; 8]) -> UnitResult {
+        self.lcd.create_char(idx, lines)
+    }
+
+    fn height(&self) -> usize {
+        self.lcd.height()
+    }
+
+    fn width(&self) -> usize {
+        self.lcd.width()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Keeps the characters sent to it, instead of showing them.
+    #[derive(Default)]
+    struct RecordingLcd {
+        moves: usize,
+        chars: Vec<u8>,
+    }
+
+    impl Panel for RecordingLcd {}
+
+    impl Hd44780 for RecordingLcd {
+        fn clear(&mut self) -> UnitResult {
+            Ok(())
+        }
+
+        fn home(&mut self) -> UnitResult {
+            Ok(())
+        }
+
+        fn move_at(&mut self, _y: usize, _x: usize) -> UnitResult {
+            self.moves += 1;
+            Ok(())
+        }
+
+        fn print_char(&mut self, ch: u8) -> UnitResult {
+            self.chars.push(ch);
+            Ok(())
+        }
+
+        fn set_backlight(&mut self, _enabled: bool) -> UnitResult {
+            Ok(())
+        }
+
+        fn set_cursor_blinking(&mut self, _enabled: bool) -> UnitResult {
+            Ok(())
+        }
+
+        fn set_cursor_visible(&mut self, _enabled: bool) -> UnitResult {
+            Ok(())
+        }
+
+        fn set_text_visible(&mut self, _enabled: bool) -> UnitResult {
+            Ok(())
+        }
+
+        fn create_char(&mut self, _idx: u8, _lines: [u8; 8]) -> UnitResult {
+            Ok(())
+        }
+
+        fn height(&self) -> usize {
+            2
+        }
+
+        fn width(&self) -> usize {
+            8
+        }
+    }
+
+    #[test]
+    fn only_sends_changed_cells() {
+        let mut lcd = BufferedLcd::new(RecordingLcd::default());
+
+        lcd.print_at(0, 0, "70.1F").unwrap();
+        lcd.present().unwrap();
+        assert_eq!(lcd.lcd.chars.len(), 16);
+        assert_eq!(lcd.lcd.moves, 2);
+
+        // Same again changes nothing
+        lcd.lcd = RecordingLcd::default();
+        lcd.clear().unwrap();
+        lcd.print_at(0, 0, "70.1F").unwrap();
+        lcd.present().unwrap();
+        assert!(lcd.lcd.chars.is_empty());
+
+        lcd.print_at(0, 0, "70.3F").unwrap();
+        lcd.print_at(1, 6, "On").unwrap();
+        lcd.present().unwrap();
+        assert_eq!(lcd.lcd.chars, b"3On");
+        assert_eq!(lcd.lcd.moves, 2);
+    }
+}
@@ -0,0 +1,195 @@
+use super::StatusView;
+
+/// Small status symbols, one character cell each.
+///
+/// Declared in priority order, for when there are more to show than free CGRAM slots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(super) enum Icon {
+    Flame,
+    SensorFault,
+    Alarm,
+    MqttOffline,
+    Hold,
+    Preheat,
+    Schedule,
+    MqttConnected,
+}
+
+const ALL: [Icon; 8] = [
+    Icon::Flame,
+    Icon::SensorFault,
+    Icon::Alarm,
+    Icon::MqttOffline,
+    Icon::Hold,
+    Icon::Preheat,
+    Icon::Schedule,
+    Icon::MqttConnected,
+];
+
+impl Icon {
+    /// Pixel rows, five pixels wide in the low bits.
+    pub(super) fn bitmap(self) -> [u8; 8] {
+        match self {
+            Icon::Flame => [
+                0b00100, 0b00110, 0b01110, 0b01111, 0b11011, 0b11011, 0b01110, 0b00000,
+            ],
+            // Thermometer
+            Icon::SensorFault => [
+                0b00100, 0b01010, 0b01010, 0b01010, 0b01110, 0b11111, 0b11111, 0b01110,
+            ],
+            // Bell
+            Icon::Alarm => [
+                0b00100, 0b01110, 0b01110, 0b01110, 0b11111, 0b00000, 0b00100, 0b00000,
+            ],
+            Icon::MqttOffline => [
+                0b00000, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b00000, 0b00000,
+            ],
+            // Padlock
+            Icon::Hold => [
+                0b01110, 0b10001, 0b10001, 0b11111, 0b11011, 0b11011, 0b11111, 0b00000,
+            ],
+            // Up arrow
+            Icon::Preheat => [
+                0b00100, 0b01110, 0b10101, 0b00100, 0b00100, 0b00100, 0b00100, 0b00000,
+            ],
+            // Clock
+            Icon::Schedule => [
+                0b00000, 0b01110, 0b10101, 0b10111, 0b10001, 0b01110, 0b00000, 0b00000,
+            ],
+            // Signal bars
+            Icon::MqttConnected => [
+                0b00000, 0b00001, 0b00001, 0b00101, 0b00101, 0b10101, 0b10101, 0b00000,
+            ],
+        }
+    }
+
+    /// The icon drawn by a CGRAM bitmap, if it's one of these.
+    pub(super) fn from_bitmap(bitmap: &[u8; 8]) -> Option<Self> {
+        ALL.iter().copied().find(|icon| icon.bitmap() == *bitmap)
+    }
+
+    /// Plain character shown when there's no CGRAM slot free.
+    pub(super) fn fallback(self) -> u8 {
+        match self {
+            Icon::Flame => b'*',
+            Icon::SensorFault => b'?',
+            Icon::Alarm => b'!',
+            Icon::MqttOffline => b'x',
+            Icon::Hold => b'H',
+            Icon::Preheat => b'^',
+            Icon::Schedule => b'S',
+            Icon::MqttConnected => b'M',
+        }
+    }
+}
+
+/// Icons for the status screen, in screen order: schedule, early start or hold, MQTT, alarms and
+/// heating.
+///
+/// `None` leaves that cell blank.
+pub(super) fn status_icons(view: &StatusView) -> [Option<Icon>; 4] {
+    let program = if view.held {
+        Some(Icon::Hold)
+    } else if view.preheating {
+        Some(Icon::Preheat)
+    } else if view.scheduled {
+        Some(Icon::Schedule)
+    } else {
+        None
+    };
+    let mqtt = view.mqtt_connected.map(|connected| {
+        if connected {
+            Icon::MqttConnected
+        } else {
+            Icon::MqttOffline
+        }
+    });
+    let alarm = if view.temperature.is_none() {
+        Some(Icon::SensorFault)
+    } else if view.alarm {
+        Some(Icon::Alarm)
+    } else {
+        None
+    };
+    let heating = if view.running {
+        Some(Icon::Flame)
+    } else {
+        None
+    };
+
+    [program, mqtt, alarm, heating]
+}
+
+/// Tracks which icons are loaded in the CGRAM slots a layout has spare.
+#[derive(Debug)]
+pub(super) struct IconSlots {
+    slots: &'static [u8],
+    loaded: Vec<Option<Icon>>,
+}
+
+impl IconSlots {
+    pub(super) fn new(slots: &'static [u8]) -> Self {
+        Self {
+            slots,
+            loaded: vec![None; slots.len()],
+        }
+    }
+
+    /// Make room for the highest priority `icons`, returning the slots that need loading.
+    ///
+    /// Icons already loaded keep their slot, so characters on screen don't change under them.
+    pub(super) fn allocate(&mut self, icons: &[Icon]) -> Vec<(u8, Icon)> {
+        let mut wanted = icons.to_vec();
+        wanted.sort();
+        wanted.dedup();
+        wanted.truncate(self.slots.len());
+
+        let mut loads = Vec::new();
+        for &icon in &wanted {
+            if self.loaded.contains(&Some(icon)) {
+                continue;
+            }
+
+            let free = self
+                .loaded
+                .iter()
+                .position(|loaded| !matches!(loaded, Some(other) if wanted.contains(other)));
+            if let Some(free) = free {
+                self.loaded[free] = Some(icon);
+                loads.push((self.slots[free], icon));
+            }
+        }
+
+        loads
+    }
+
+    /// The character to print for `icon`, its slot if loaded or a plain fallback.
+    pub(super) fn code(&self, icon: Icon) -> u8 {
+        self.loaded
+            .iter()
+            .position(|&loaded| loaded == Some(icon))
+            .map_or(icon.fallback(), |index| self.slots[index])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn highest_priority_icons_get_slots() {
+        let mut slots = IconSlots::new(&[0, 7]);
+
+        let loads = slots.allocate(&[Icon::Schedule, Icon::MqttConnected, Icon::Flame]);
+        assert_eq!(loads, vec![(0, Icon::Flame), (7, Icon::Schedule)]);
+        assert_eq!(slots.code(Icon::Flame), 0);
+        assert_eq!(slots.code(Icon::MqttConnected), b'M');
+
+        // Flame keeps its slot while the alarm takes over the schedule's
+        let loads = slots.allocate(&[Icon::Schedule, Icon::Alarm, Icon::Flame]);
+        assert_eq!(loads, vec![(7, Icon::Alarm)]);
+        assert_eq!(slots.code(Icon::Schedule), b'S');
+
+        assert!(slots.allocate(&[Icon::Flame]).is_empty());
+    }
+}
@@ -0,0 +1,269 @@
+use super::font;
+use super::icons::{self, Icon, IconSlots};
+use super::memory::MemoryLcd;
+use super::{Screen, StatusView};
+use crate::error::Result;
+use pwr_hd44780::{Hd44780, UnitResult};
+
+/// Target, humidity and clock are stacked to the right of the big temperature.
+const SIDE_COLUMN: usize = 15;
+const SIDE_WIDTH: usize = 5;
+/// Icons go in the bottom right corner.
+const ICONS_COLUMN: usize = 16;
+/// The compact layout's target follows the temperature on the top line.
+const COMPACT_TARGET_COLUMN: usize = 7;
+const COMPACT_ICONS_COLUMN: usize = 12;
+/// A plain character panel has all of CGRAM free for icons.
+const ALL_SLOTS: [u8; 8] = [0, 1, 2, 3, 4, 5, 6, 7];
+
+/// A character panel the layouts can draw on.
+pub(super) trait Panel: Hd44780 {
+    /// Called once all updates for an event have been drawn.
+    fn present(&mut self) -> UnitResult {
+        Ok(())
+    }
+}
+
+impl Panel for pwr_hd44780::DirectLcd {}
+
+impl Panel for MemoryLcd {
+    fn present(&mut self) -> UnitResult {
+        if self.echo() && self.take_changed() {
+            println!("{}", self.render());
+        }
+
+        Ok(())
+    }
+}
+
+/// 20x4 layout with the temperature in big digits.
+pub(super) struct LargeLcd<T: Panel> {
+    pub(super) lcd: T,
+    slots: IconSlots,
+}
+
+impl<T: Panel> LargeLcd<T> {
+    pub(super) fn new(mut lcd: T) -> Result<Self> {
+        font::setup(&mut lcd)?;
+
+        Ok(Self {
+            lcd,
+            slots: IconSlots::new(&font::FREE_SLOTS),
+        })
+    }
+}
+
+impl<T: Panel> Screen for LargeLcd<T> {
+    fn draw_status(&mut self, view: &StatusView) -> Result<()> {
+        font::print_big_number(&mut self.lcd, view.temperature, 0, 0)?;
+
+        self.lcd
+            .print_at(1, SIDE_COLUMN, side_value(view.humidity, '%'))?;
+        self.lcd
+            .print_at(2, SIDE_COLUMN, view.time.format("%l:%M").to_string())?;
+
+        let icons = icons::status_icons(view);
+        print_icons(&mut self.lcd, &mut self.slots, &icons, 3, ICONS_COLUMN)?;
+
+        self.draw_target(view)
+    }
+
+    fn draw_target(&mut self, view: &StatusView) -> Result<()> {
+        let text = match view.target {
+            Some(target) => side_value(target, view.units.symbol()),
+            None => " ".repeat(SIDE_WIDTH),
+        };
+        self.lcd.print_at(0, SIDE_COLUMN, text)?;
+
+        Ok(())
+    }
+
+    fn draw_menu(&mut self, rows: &[String]) -> Result<()> {
+        print_rows(&mut self.lcd, rows)
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        Ok(self.lcd.clear()?)
+    }
+
+    fn set_backlight(&mut self, on: bool) -> Result<()> {
+        Ok(self.lcd.set_backlight(on)?)
+    }
+
+    fn present(&mut self) -> Result<()> {
+        Ok(self.lcd.present()?)
+    }
+}
+
+/// 16x2 layout in plain characters: temperature and target on top, humidity, clock and run
+/// status below.
+pub(super) struct CompactLcd<T: Panel> {
+    pub(super) lcd: T,
+    slots: IconSlots,
+}
+
+impl<T: Panel> CompactLcd<T> {
+    pub(super) fn new(lcd: T) -> Self {
+        Self {
+            lcd,
+            slots: IconSlots::new(&ALL_SLOTS),
+        }
+    }
+}
+
+impl<T: Panel> Screen for CompactLcd<T> {
+    fn draw_status(&mut self, view: &StatusView) -> Result<()> {
+        let temperature = match view.temperature {
+            Some(temperature) => side_value(temperature, view.units.symbol()),
+            None => format!("--.-{}", view.units.symbol()),
+        };
+        self.lcd.print_at(0, 0, format!("{:<7}", temperature))?;
+
+        let bottom = format!(
+            "{}{:>6} ",
+            side_value(view.humidity, '%'),
+            view.time.format("%l:%M").to_string(),
+        );
+        self.lcd.print_at(1, 0, bottom)?;
+
+        let icons = icons::status_icons(view);
+        print_icons(
+            &mut self.lcd,
+            &mut self.slots,
+            &icons,
+            1,
+            COMPACT_ICONS_COLUMN,
+        )?;
+
+        self.draw_target(view)
+    }
+
+    fn draw_target(&mut self, view: &StatusView) -> Result<()> {
+        let text = match view.target {
+            Some(target) => format!("Set {}", side_value(target, view.units.symbol())),
+            None => " ".repeat(SIDE_WIDTH + 4),
+        };
+        self.lcd.print_at(0, COMPACT_TARGET_COLUMN, text)?;
+
+        Ok(())
+    }
+
+    /// Only the title and value fit, the hints are left off.
+    fn draw_menu(&mut self, rows: &[String]) -> Result<()> {
+        print_rows(&mut self.lcd, rows)
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        Ok(self.lcd.clear()?)
+    }
+
+    fn set_backlight(&mut self, on: bool) -> Result<()> {
+        Ok(self.lcd.set_backlight(on)?)
+    }
+
+    fn present(&mut self) -> Result<()> {
+        Ok(self.lcd.present()?)
+    }
+}
+
+/// Print a row of icons, loading any that need a CGRAM slot first.
+fn print_icons(
+    lcd: &mut impl Hd44780,
+    slots: &mut IconSlots,
+    icons: &[Option<Icon>],
+    row: usize,
+    col: usize,
+) -> Result<()> {
+    let shown: Vec<Icon> = icons.iter().flatten().copied().collect();
+    for (slot, icon) in slots.allocate(&shown) {
+        lcd.create_char(slot, icon.bitmap())?;
+    }
+
+    lcd.move_at(row, col)?;
+    for icon in icons {
+        lcd.print_char(icon.map_or(b' ', |icon| slots.code(icon)))?;
+    }
+
+    Ok(())
+}
+
+/// Print as many rows as fit, cut to the panel width.
+fn print_rows(lcd: &mut impl Hd44780, rows: &[String]) -> Result<()> {
+    let width = lcd.width();
+
+    for (row, text) in rows.iter().take(lcd.height()).enumerate() {
+        lcd.print_at(row, 0, format!("{:<width$.width$}", text, width = width))?;
+    }
+
+    Ok(())
+}
+
+/// A value for the side column, dropping the tenths if it doesn't fit.
+pub(super) fn side_value(value: f32, suffix: char) -> String {
+    let text = format!("{:.1}{}", value, suffix);
+    let text = if text.len() > SIDE_WIDTH {
+        format!("{:.0}{}", value, suffix)
+    } else {
+        text
+    };
+
+    format!("{:>width$.width$}", text, width = SIDE_WIDTH)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Units;
+    use chrono::{Local, TimeZone};
+
+    fn view(temperature: Option<f32>, target: Option<f32>) -> StatusView {
+        StatusView {
+            temperature,
+            target,
+            units: Units::Fahrenheit,
+            humidity: 40.0,
+            time: Local.ymd(2020, 1, 2).and_hms(9, 41, 0),
+            running: false,
+            held: false,
+            scheduled: true,
+            preheating: false,
+            mqtt_connected: Some(false),
+            alarm: false,
+        }
+    }
+
+    #[test]
+    fn compact_layout() {
+        let mut compact = CompactLcd::new(MemoryLcd::new(16, 2));
+
+        compact.draw_status(&view(Some(70.26), Some(68.0))).unwrap();
+        assert_eq!(
+            compact.lcd.rows(),
+            vec!["70.3F  Set 68.0F", "40.0%  9:41 Sx  "]
+        );
+
+        let preheating = StatusView {
+            preheating: true,
+            ..view(Some(66.0), Some(68.0))
+        };
+        compact.draw_status(&preheating).unwrap();
+        assert_eq!(
+            compact.lcd.rows(),
+            vec!["66.0F  Set 68.0F", "40.0%  9:41 ^x  "]
+        );
+
+        compact.draw_status(&view(None, None)).unwrap();
+        assert_eq!(
+            compact.lcd.rows(),
+            vec!["--.-F           ", "40.0%  9:41 Sx? "]
+        );
+
+        let menu: Vec<String> = vec!["Backlight timeout   4/7".into(), "30s".into(), "".into()];
+        compact.clear().unwrap();
+        compact.draw_menu(&menu).unwrap();
+        assert_eq!(
+            compact.lcd.rows(),
+            vec!["Backlight timeou", "30s             "]
+        );
+    }
+}
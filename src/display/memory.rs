@@ -0,0 +1,219 @@
+use super::icons::Icon;
+use pwr_hd44780::{Hd44780, UnitResult};
+
+const BLANK: u8 = 254;
+const FULL_BLOCK: u8 = 255;
+
+/// An `Hd44780` that keeps the screen contents in memory, for tests and running without a panel.
+///
+/// Unlike the real panel, writing past the end of a line is an error so layouts that overflow
+/// get caught.
+#[derive(Debug, Clone)]
+pub(crate) struct MemoryLcd {
+    width: usize,
+    height: usize,
+    cells: Vec<Vec<u8>>,
+    cursor: (usize, usize),
+    cgram: [[u8; 8]; 8],
+    backlight: bool,
+    changed: bool,
+    echo: bool,
+}
+
+impl MemoryLcd {
+    pub(crate) fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            cells: vec![vec![b' '; width]; height],
+            cursor: (0, 0),
+            cgram: [[0; 8]; 8],
+            backlight: false,
+            changed: false,
+            echo: false,
+        }
+    }
+
+    /// Like `new`, but the screen is printed to the terminal whenever it changes.
+    pub(crate) fn terminal(width: usize, height: usize) -> Self {
+        Self {
+            echo: true,
+            ..Self::new(width, height)
+        }
+    }
+
+    pub(crate) fn echo(&self) -> bool {
+        self.echo
+    }
+
+    /// Screen contents as text, one string per line.
+    ///
+    /// Icons are shown as their plain fallback characters. Other custom characters are
+    /// approximated with half blocks, filled where at least half of that half of the character's
+    /// pixels are set.
+    pub(crate) fn rows(&self) -> Vec<String> {
+        self.cells
+            .iter()
+            .map(|row| row.iter().map(|&code| self.cell_char(code)).collect())
+            .collect()
+    }
+
+    /// The screen with a border, for printing to a terminal.
+    pub(crate) fn render(&self) -> String {
+        let border = format!("+{}+", "-".repeat(self.width));
+        let mut out = border.clone();
+
+        for row in self.rows() {
+            out.push_str(&format!("\n|{}|", row));
+        }
+        out.push('\n');
+        out.push_str(&border);
+        if !self.backlight {
+            out.push_str(" (backlight off)");
+        }
+
+        out
+    }
+
+    /// Character code at a position.
+    pub(crate) fn cell(&self, row: usize, col: usize) -> u8 {
+        self.cells[row][col]
+    }
+
+    /// Whether anything has changed since the last call.
+    pub(crate) fn take_changed(&mut self) -> bool {
+        std::mem::replace(&mut self.changed, false)
+    }
+
+    fn cell_char(&self, code: u8) -> char {
+        match code {
+            0..=7 => {
+                let bitmap = &self.cgram[code as usize];
+                if let Some(icon) = Icon::from_bitmap(bitmap) {
+                    return icon.fallback() as char;
+                }
+
+                let filled = |lines: &[u8]| {
+                    let pixels: u32 = lines.iter().map(|line| (line & 0b11111).count_ones()).sum();
+                    pixels * 2 >= 5 * lines.len() as u32
+                };
+
+                match (filled(&bitmap[..4]), filled(&bitmap[4..])) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                }
+            }
+            BLANK => ' ',
+            FULL_BLOCK => '█',
+            b' '..=b'~' => code as char,
+            _ => '?',
+        }
+    }
+}
+
+impl Hd44780 for MemoryLcd {
+    fn clear(&mut self) -> UnitResult {
+        for row in &mut self.cells {
+            for cell in row.iter_mut() {
+                *cell = b' ';
+            }
+        }
+        self.cursor = (0, 0);
+        self.changed = true;
+
+        Ok(())
+    }
+
+    fn home(&mut self) -> UnitResult {
+        self.cursor = (0, 0);
+
+        Ok(())
+    }
+
+    fn move_at(&mut self, y: usize, x: usize) -> UnitResult {
+        if y >= self.height || x >= self.width {
+            return Err(format!("position ({}, {}) is off the screen", y, x).into());
+        }
+        self.cursor = (y, x);
+
+        Ok(())
+    }
+
+    fn print_char(&mut self, ch: u8) -> UnitResult {
+        let (y, x) = self.cursor;
+        if x >= self.width {
+            return Err(format!("line {} overflowed", y).into());
+        }
+
+        if self.cells[y][x] != ch {
+            self.cells[y][x] = ch;
+            self.changed = true;
+        }
+        self.cursor = (y, x + 1);
+
+        Ok(())
+    }
+
+    fn set_backlight(&mut self, enabled: bool) -> UnitResult {
+        self.changed |= self.backlight != enabled;
+        self.backlight = enabled;
+
+        Ok(())
+    }
+
+    fn set_cursor_blinking(&mut self, _enabled: bool) -> UnitResult {
+        Ok(())
+    }
+
+    fn set_cursor_visible(&mut self, _enabled: bool) -> UnitResult {
+        Ok(())
+    }
+
+    fn set_text_visible(&mut self, _enabled: bool) -> UnitResult {
+        Ok(())
+    }
+
+    fn create_char(&mut self, idx: u8, lines: [u8; 8]) -> UnitResult {
+        let slot = self
+            .cgram
+            .get_mut(idx as usize)
+            .ok_or_else(|| format!("custom character {} out of range", idx))?;
+        *slot = lines;
+        self.changed = true;
+
+        Ok(())
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn width(&self) -> usize {
+        self.width
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_text_and_custom_characters() {
+        let mut lcd = MemoryLcd::new(4, 2);
+
+        lcd.create_char(0, [0b11111, 0b11111, 0b11111, 0, 0, 0, 0, 0])
+            .unwrap();
+        lcd.print_at(0, 0, "ab").unwrap();
+        lcd.move_at(1, 1).unwrap();
+        lcd.print_char(0).unwrap();
+        lcd.print_char(FULL_BLOCK).unwrap();
+
+        assert_eq!(lcd.rows(), vec!["ab  ", " ▀█ "]);
+        assert!(lcd.take_changed());
+        assert!(!lcd.take_changed());
+        assert!(lcd.print_at(1, 2, "abc").is_err());
+        assert!(lcd.move_at(2, 0).is_err());
+    }
+}
@@ -0,0 +1,377 @@
+use super::font;
+use super::icons::{self, Icon};
+use super::lcd::side_value;
+use super::{Screen, StatusView};
+use crate::error::Result;
+use embedded_graphics::image::{Image, ImageRaw};
+use embedded_graphics::mono_font::ascii::{FONT_10X20, FONT_6X10};
+use embedded_graphics::mono_font::{MonoFont, MonoTextStyle, MonoTextStyleBuilder};
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+use embedded_graphics::text::{Baseline, Text};
+use std::convert::Infallible;
+
+const WIDTH: usize = 128;
+const HEIGHT: usize = 64;
+/// Characters per menu line in the small font.
+pub(super) const COLUMNS: usize = 21;
+const MENU_LINE_HEIGHT: i32 = 16;
+
+/// The big temperature uses the LCD font's character cells, one pixel per dot with no gaps.
+const CELL_SIZE: Size = Size::new(5, 8);
+const TEMPERATURE_ORIGIN: Point = Point::new(0, 2);
+/// Target, humidity and clock are stacked to the right of the temperature, in the small font.
+const SIDE_X: i32 = 80;
+const SIDE_CHARS: usize = 8;
+const TARGET_Y: i32 = 2;
+const HUMIDITY_Y: i32 = 14;
+const CLOCK_Y: i32 = 26;
+/// Run status along the bottom, with the flame while heating.
+const FLAME_ORIGIN: Point = Point::new(0, 44);
+const RUN_STATUS_ORIGIN: Point = Point::new(22, 42);
+/// The other icons sit to the right of the run status, drawn at double size.
+const ICONS_ORIGIN: Point = Point::new(96, 46);
+const ICON_PITCH: i32 = 11;
+const ICON_SCALE: u32 = 2;
+
+const FLAME: [u8; 32] = [
+    0x00, 0x80, 0x01, 0x80, 0x01, 0xC0, 0x03, 0xC0, 0x03, 0xE0, 0x07, 0xE0, 0x0F, 0xE4, 0x0F, 0xF6,
+    0x1E, 0xFE, 0x1C, 0x7E, 0x3C, 0x7E, 0x38, 0x3C, 0x38, 0x3C, 0x1C, 0x78, 0x0F, 0xF8, 0x03, 0xE0,
+];
+const DROP: [u8; 8] = [0x10, 0x10, 0x38, 0x7C, 0x7C, 0x7C, 0x38, 0x00];
+
+/// SSD1306 setup for a 128x64 panel running off its internal charge pump.
+const INIT: [u8; 25] = [
+    0xAE, // display off
+    0xD5, 0x80, // clock divide
+    0xA8, 0x3F, // multiplex, 64 lines
+    0xD3, 0x00, // no display offset
+    0x40, // start line 0
+    0x8D, 0x14, // charge pump on
+    0x20, 0x00, // horizontal addressing
+    0xA1, // columns mirrored
+    0xC8, // rows mirrored
+    0xDA, 0x12, // alternative COM pins
+    0x81, 0xCF, // contrast
+    0xD9, 0xF1, // pre-charge
+    0xDB, 0x40, // VCOMH deselect level
+    0xA4, // show RAM contents
+    0xA6, // not inverted
+    0xAF, // display on
+];
+const DISPLAY_ON: u8 = 0xAF;
+const DISPLAY_OFF: u8 = 0xAE;
+/// Control bytes leading each I2C write
+const COMMAND: u8 = 0x00;
+const DATA: u8 = 0x40;
+/// Keep writes short for I2C adapters with small buffers
+const DATA_CHUNK: usize = 32;
+
+/// How the OLED is attached.
+pub(super) trait OledBus {
+    fn write(&mut self, bytes: &[u8]) -> Result<()>;
+}
+
+impl OledBus for rppal::i2c::I2c {
+    fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        rppal::i2c::I2c::write(self, bytes)?;
+
+        Ok(())
+    }
+}
+
+/// Pixels in the SSD1306's layout: a byte per column of eight rows, a page of rows at a time.
+struct FrameBuffer {
+    pixels: [u8; WIDTH * HEIGHT / 8],
+    changed: bool,
+}
+
+impl FrameBuffer {
+    fn new() -> Self {
+        Self {
+            pixels: [0; WIDTH * HEIGHT / 8],
+            changed: true,
+        }
+    }
+
+    #[cfg(test)]
+    fn is_lit(&self, x: usize, y: usize) -> bool {
+        self.pixels[x + y / 8 * WIDTH] & (1 << (y % 8)) != 0
+    }
+}
+
+impl OriginDimensions for FrameBuffer {
+    fn size(&self) -> Size {
+        Size::new(WIDTH as u32, HEIGHT as u32)
+    }
+}
+
+impl DrawTarget for FrameBuffer {
+    type Color = BinaryColor;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> std::result::Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            if point.x < 0 || point.y < 0 {
+                continue;
+            }
+            let (x, y) = (point.x as usize, point.y as usize);
+            if x >= WIDTH || y >= HEIGHT {
+                continue;
+            }
+
+            let byte = &mut self.pixels[x + y / 8 * WIDTH];
+            let bit = 1 << (y % 8);
+            let old = *byte;
+            match color {
+                BinaryColor::On => *byte |= bit,
+                BinaryColor::Off => *byte &= !bit,
+            }
+            self.changed |= *byte != old;
+        }
+
+        Ok(())
+    }
+}
+
+/// 128x64 SSD1306 OLED, drawn in memory and sent over once everything for an event is drawn.
+///
+/// There's no backlight, turning it off blanks the panel instead.
+pub(super) struct Oled<B: OledBus> {
+    bus: B,
+    frame: FrameBuffer,
+}
+
+impl<B: OledBus> Oled<B> {
+    pub(super) fn new(mut bus: B) -> Result<Self> {
+        bus.write(&command(&INIT))?;
+
+        Ok(Self {
+            bus,
+            frame: FrameBuffer::new(),
+        })
+    }
+
+    fn text(&mut self, text: &str, position: Point, font: &MonoFont) {
+        let style: MonoTextStyle<BinaryColor> = MonoTextStyleBuilder::new()
+            .font(font)
+            .text_color(BinaryColor::On)
+            .background_color(BinaryColor::Off)
+            .build();
+
+        Text::with_baseline(text, position, style, Baseline::Top)
+            .draw(&mut self.frame)
+            .ok();
+    }
+
+    fn icon(&mut self, data: &[u8], width: u32, position: Point, visible: bool) {
+        let raw = ImageRaw::<BinaryColor>::new(data, width);
+
+        if visible {
+            Image::new(&raw, position).draw(&mut self.frame).ok();
+        } else {
+            let area = Rectangle::new(position, raw.size());
+            self.frame.fill_solid(&area, BinaryColor::Off).ok();
+        }
+    }
+
+    fn big_number(&mut self, value: Option<f32>) {
+        for (line, codes) in font::big_number_cells(value).iter().enumerate() {
+            for (col, &code) in codes.iter().enumerate() {
+                let corner = TEMPERATURE_ORIGIN
+                    + Point::new(
+                        col as i32 * CELL_SIZE.width as i32,
+                        line as i32 * CELL_SIZE.height as i32,
+                    );
+                self.cell(&font::cell_pixels(code), corner, 1);
+            }
+        }
+    }
+
+    /// Draw a character cell bitmap, each dot `scale` pixels square.
+    fn cell(&mut self, bitmap: &[u8; 8], corner: Point, scale: u32) {
+        for (y, bits) in bitmap.iter().enumerate() {
+            for x in 0..CELL_SIZE.width as i32 {
+                let lit = bits & (0b10000 >> x) != 0;
+                let dot = Rectangle::new(
+                    corner + Point::new(x, y as i32) * scale as i32,
+                    Size::new(scale, scale),
+                );
+                self.frame.fill_solid(&dot, lit.into()).ok();
+            }
+        }
+    }
+}
+
+impl<B: OledBus> Screen for Oled<B> {
+    fn draw_status(&mut self, view: &StatusView) -> Result<()> {
+        self.big_number(view.temperature);
+
+        self.icon(&DROP, 8, Point::new(SIDE_X, HUMIDITY_Y + 1), true);
+        let humidity = format!(
+            "{:>width$}",
+            side_value(view.humidity, '%'),
+            width = SIDE_CHARS - 2
+        );
+        self.text(&humidity, Point::new(SIDE_X + 12, HUMIDITY_Y), &FONT_6X10);
+
+        let clock = format!("{:>width$}", view.time.format("%l:%M"), width = SIDE_CHARS);
+        self.text(&clock, Point::new(SIDE_X, CLOCK_Y), &FONT_6X10);
+
+        self.icon(&FLAME, 16, FLAME_ORIGIN, view.running);
+        let run_status = if view.running { "Heating" } else { "Idle" };
+        self.text(
+            &format!("{:<7}", run_status),
+            RUN_STATUS_ORIGIN,
+            &FONT_10X20,
+        );
+
+        // The flame is drawn large on its own
+        let [program, mqtt, alarm, _] = icons::status_icons(view);
+        for (i, icon) in [program, mqtt, alarm].iter().enumerate() {
+            let corner = ICONS_ORIGIN + Point::new(i as i32 * ICON_PITCH, 0);
+            let bitmap = icon.map_or([0; 8], Icon::bitmap);
+            self.cell(&bitmap, corner, ICON_SCALE);
+        }
+
+        self.draw_target(view)
+    }
+
+    fn draw_target(&mut self, view: &StatusView) -> Result<()> {
+        let text = match view.target {
+            Some(target) => format!("Set{}", side_value(target, view.units.symbol())),
+            None => " ".repeat(SIDE_CHARS),
+        };
+        self.text(&text, Point::new(SIDE_X, TARGET_Y), &FONT_6X10);
+
+        Ok(())
+    }
+
+    fn draw_menu(&mut self, rows: &[String]) -> Result<()> {
+        let lines = HEIGHT / MENU_LINE_HEIGHT as usize;
+
+        for (line, text) in rows.iter().take(lines).enumerate() {
+            let text = format!("{:<width$.width$}", text, width = COLUMNS);
+            let position = Point::new(0, line as i32 * MENU_LINE_HEIGHT);
+            self.text(&text, position, &FONT_6X10);
+        }
+
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.frame.clear(BinaryColor::Off).ok();
+
+        Ok(())
+    }
+
+    fn set_backlight(&mut self, on: bool) -> Result<()> {
+        self.bus
+            .write(&command(&[if on { DISPLAY_ON } else { DISPLAY_OFF }]))
+    }
+
+    fn present(&mut self) -> Result<()> {
+        if !self.frame.changed {
+            return Ok(());
+        }
+
+        // Whole screen, in the same order as the frame buffer
+        self.bus
+            .write(&command(&[0x21, 0, WIDTH as u8 - 1, 0x22, 0, 7]))?;
+        for chunk in self.frame.pixels.chunks(DATA_CHUNK) {
+            let mut bytes = Vec::with_capacity(chunk.len() + 1);
+            bytes.push(DATA);
+            bytes.extend_from_slice(chunk);
+            self.bus.write(&bytes)?;
+        }
+        self.frame.changed = false;
+
+        Ok(())
+    }
+}
+
+fn command(bytes: &[u8]) -> Vec<u8> {
+    let mut command = Vec::with_capacity(bytes.len() + 1);
+    command.push(COMMAND);
+    command.extend_from_slice(bytes);
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Units;
+    use chrono::{Local, TimeZone};
+
+    #[derive(Default)]
+    struct RecordingBus {
+        writes: Vec<Vec<u8>>,
+    }
+
+    impl OledBus for RecordingBus {
+        fn write(&mut self, bytes: &[u8]) -> Result<()> {
+            self.writes.push(bytes.to_vec());
+
+            Ok(())
+        }
+    }
+
+    fn view(target: Option<f32>) -> StatusView {
+        StatusView {
+            temperature: Some(70.3),
+            target,
+            units: Units::Fahrenheit,
+            humidity: 40.0,
+            time: Local.ymd(2020, 1, 2).and_hms(9, 41, 0),
+            running: true,
+            held: false,
+            scheduled: true,
+            preheating: false,
+            mqtt_connected: Some(true),
+            alarm: false,
+        }
+    }
+
+    fn lit(frame: &FrameBuffer, area: Rectangle) -> usize {
+        area.points()
+            .filter(|point| frame.is_lit(point.x as usize, point.y as usize))
+            .count()
+    }
+
+    #[test]
+    fn draws_status_and_blanks_flashing_target() {
+        let mut oled = Oled::new(RecordingBus::default()).unwrap();
+        let temperature = Rectangle::new(TEMPERATURE_ORIGIN, Size::new(70, 32));
+        let target = Rectangle::new(Point::new(SIDE_X, TARGET_Y), Size::new(48, 10));
+
+        oled.draw_status(&view(Some(70.0))).unwrap();
+        assert!(lit(&oled.frame, temperature) > 0);
+        assert!(lit(&oled.frame, target) > 0);
+        assert!(lit(&oled.frame, Rectangle::new(FLAME_ORIGIN, Size::new(16, 16))) > 0);
+
+        oled.draw_target(&view(None)).unwrap();
+        assert_eq!(lit(&oled.frame, target), 0);
+    }
+
+    #[test]
+    fn sends_the_frame_only_when_changed() {
+        let mut oled = Oled::new(RecordingBus::default()).unwrap();
+
+        oled.draw_status(&view(Some(70.0))).unwrap();
+        oled.present().unwrap();
+        oled.draw_status(&view(Some(70.0))).unwrap();
+        oled.present().unwrap();
+
+        let writes = &oled.bus.writes;
+        let data: Vec<_> = writes.iter().filter(|w| w[0] == DATA).collect();
+        assert_eq!(writes[0], command(&INIT));
+        assert_eq!(
+            data.iter().map(|w| w.len() - 1).sum::<usize>(),
+            WIDTH * HEIGHT / 8
+        );
+    }
+}
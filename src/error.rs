@@ -0,0 +1,73 @@
+use std::fmt;
+use std::io;
+
+pub(crate) type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub(crate) enum Error {
+    /// Missing or invalid configuration, e.g. MQTT credentials or broker address
+    Config(String),
+    Gpio(rppal::gpio::Error),
+    Http(hyper::Error),
+    Io(io::Error),
+    /// Failure talking to the LCD, the underlying driver error isn't `Send` so only the message
+    /// is kept
+    Display(String),
+    /// The task on the other end of a channel has gone away
+    ChannelClosed(&'static str),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Config(message) => write!(f, "Configuration error: {}", message),
+            Error::Gpio(e) => write!(f, "GPIO error: {}", e),
+            Error::Http(e) => write!(f, "HTTP error: {}", e),
+            Error::Io(e) => write!(f, "IO error: {}", e),
+            Error::Display(message) => write!(f, "Display error: {}", message),
+            Error::ChannelClosed(channel) => write!(f, "Channel closed: {}", channel),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Gpio(e) => Some(e),
+            Error::Http(e) => Some(e),
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<rppal::gpio::Error> for Error {
+    fn from(e: rppal::gpio::Error) -> Self {
+        Error::Gpio(e)
+    }
+}
+
+impl From<hyper::Error> for Error {
+    fn from(e: hyper::Error) -> Self {
+        Error::Http(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<rppal::i2c::Error> for Error {
+    fn from(e: rppal::i2c::Error) -> Self {
+        Error::Display(e.to_string())
+    }
+}
+
+// The LCD driver reports all of its failures as boxed errors
+impl From<Box<dyn std::error::Error>> for Error {
+    fn from(e: Box<dyn std::error::Error>) -> Self {
+        Error::Display(e.to_string())
+    }
+}
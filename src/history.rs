@@ -0,0 +1,119 @@
+use crate::Status;
+use chrono::{DateTime, Duration, Local};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// How often a sample is kept, readings in between are dropped.
+const SAMPLE_INTERVAL_SECS: i64 = 60;
+/// A day of samples.
+const CAPACITY: usize = 24 * 60;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Sample {
+    pub(crate) timestamp: DateTime<Local>,
+    pub(crate) temperature: f32,
+    pub(crate) humidity: f32,
+    pub(crate) target_temperature: f32,
+    pub(crate) running: bool,
+    /// For comparing heating against the weather, `None` when unknown
+    pub(crate) outdoor_temperature: Option<f32>,
+}
+
+/// In-memory record of recent readings, shared between the control loop and the HTTP server.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct History {
+    samples: Arc<Mutex<VecDeque<Sample>>>,
+}
+
+impl History {
+    pub(crate) fn record(&self, status: &Status) {
+        self.record_at(status, Local::now());
+    }
+
+    fn record_at(&self, status: &Status, timestamp: DateTime<Local>) {
+        // A failing sensor's last reading isn't worth graphing
+        if status.sensor_fault {
+            return;
+        }
+
+        let mut samples = self.lock();
+
+        if let Some(last) = samples.back() {
+            if timestamp - last.timestamp < Duration::seconds(SAMPLE_INTERVAL_SECS) {
+                return;
+            }
+        }
+
+        if samples.len() == CAPACITY {
+            samples.pop_front();
+        }
+
+        samples.push_back(Sample {
+            timestamp,
+            temperature: status.temperature,
+            humidity: status.humidity,
+            target_temperature: status.target_temperature,
+            running: status.running,
+            outdoor_temperature: status.outdoor_temperature,
+        });
+    }
+
+    /// All samples, oldest first.
+    pub(crate) fn samples(&self) -> Vec<Sample> {
+        self.lock().iter().cloned().collect()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, VecDeque<Sample>> {
+        self.samples
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_one_sample_per_interval() {
+        let history = History::default();
+        let mut status = Status::new(70.0, false);
+        let start = Local::now();
+
+        // Nothing is kept until the sensor has a reading
+        history.record_at(&status, start - Duration::seconds(60));
+        assert!(history.samples().is_empty());
+
+        status.sensor_fault = false;
+        status.temperature = 68.0;
+        history.record_at(&status, start);
+        status.temperature = 68.5;
+        history.record_at(&status, start + Duration::seconds(30));
+        status.temperature = 69.0;
+        history.record_at(&status, start + Duration::seconds(60));
+
+        let temperatures: Vec<f32> = history
+            .samples()
+            .iter()
+            .map(|sample| sample.temperature)
+            .collect();
+        assert_eq!(temperatures, vec![68.0, 69.0]);
+    }
+
+    #[test]
+    fn drops_samples_older_than_a_day() {
+        let history = History::default();
+        let mut status = Status::new(70.0, false);
+        status.sensor_fault = false;
+        let start = Local::now();
+
+        for minute in 0..(CAPACITY as i64 + 10) {
+            history.record_at(&status, start + Duration::minutes(minute));
+        }
+
+        let samples = history.samples();
+        assert_eq!(samples.len(), CAPACITY);
+        assert_eq!(samples[0].timestamp, start + Duration::minutes(10));
+    }
+}
@@ -0,0 +1,300 @@
+use crate::error::Result;
+use crate::history::History;
+use crate::metrics::Metrics;
+use crate::schedule::{Hold, Schedule, ScheduleEntry};
+use crate::{Event, Mode, Status, TARGET_RANGE};
+use chrono::{Duration, Local};
+use hyper::header::{AUTHORIZATION, CACHE_CONTROL, CONTENT_TYPE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use tokio::stream::StreamExt;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::watch;
+use tracing::{debug, info};
+
+const DASHBOARD: &str = include_str!("http/dashboard.html");
+/// Longest timed hold, anything longer should be indefinite.
+const MAX_HOLD_MINUTES: i64 = 7 * 24 * 60;
+
+/// Everything request handlers need, cloned per connection.
+#[derive(Debug, Clone)]
+pub(crate) struct Context {
+    pub(crate) metrics: Metrics,
+    pub(crate) history: History,
+    pub(crate) status: watch::Receiver<Status>,
+    pub(crate) events: Sender<Event>,
+    /// When set, API requests need an `Authorization: Bearer <token>` header, or a `token` query
+    /// parameter for clients like `EventSource` that can't set headers
+    pub(crate) token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TargetRequest {
+    target_temperature: f32,
+}
+
+#[derive(Debug, Deserialize)]
+struct ModeRequest {
+    mode: Mode,
+}
+
+/// Hold for a number of minutes, or indefinitely if not given.
+#[derive(Debug, Default, Deserialize)]
+struct HoldRequest {
+    minutes: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+struct Message<'a> {
+    message: &'a str,
+}
+
+pub(crate) async fn serve(address: SocketAddr, context: Context) -> Result<()> {
+    let make_service = make_service_fn(move |_| {
+        let context = context.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let context = context.clone();
+                async move { Ok::<_, Infallible>(route(request, context).await) }
+            }))
+        }
+    });
+
+    let server = Server::try_bind(&address)?.serve(make_service);
+    info!(%address, "HTTP server listening");
+    server.await?;
+
+    Ok(())
+}
+
+async fn route(request: Request<Body>, mut context: Context) -> Response<Body> {
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    debug!(%method, %path, "HTTP request");
+
+    // Metrics are read-only and scraped by Prometheus, which we don't want to hand a token to.
+    // The dashboard page is static and passes its own token along to the API.
+    if method == Method::GET {
+        match path.as_str() {
+            "/metrics" => {
+                return Response::builder()
+                    .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+                    .body(Body::from(context.metrics.render()))
+                    .unwrap_or_default()
+            }
+            "/" => {
+                return Response::builder()
+                    .header(CONTENT_TYPE, "text/html; charset=utf-8")
+                    .body(Body::from(DASHBOARD))
+                    .unwrap_or_default()
+            }
+            _ => {}
+        }
+    }
+
+    if !authorized(&request, context.token.as_deref()) {
+        return message(StatusCode::UNAUTHORIZED, "Missing or invalid token");
+    }
+
+    let event = match (&method, path.as_str()) {
+        (&Method::GET, "/status") => {
+            let status = context.status.borrow().clone();
+            return json(StatusCode::OK, &status);
+        }
+        (&Method::GET, "/schedule") => {
+            let status = context.status.borrow();
+            return json(StatusCode::OK, &status.schedule);
+        }
+        (&Method::GET, "/history") => return json(StatusCode::OK, &context.history.samples()),
+        (&Method::GET, "/events") => return status_events(context.status),
+        (&Method::PUT, "/target") => parse_body(request)
+            .await
+            .and_then(|body: TargetRequest| check_target(body.target_temperature))
+            .map(Event::UpdateTarget),
+        (&Method::PUT, "/mode") => parse_body(request)
+            .await
+            .map(|body: ModeRequest| Event::SetMode(body.mode)),
+        (&Method::POST, "/hold") => {
+            parse_body_or_default(request)
+                .await
+                .and_then(|body: HoldRequest| match body.minutes {
+                    Some(minutes) if (1..=MAX_HOLD_MINUTES).contains(&minutes) => {
+                        Ok(Event::SetHold(Some(Hold::Until(
+                            Local::now() + Duration::minutes(minutes),
+                        ))))
+                    }
+                    Some(_) => Err(format!("minutes must be 1 to {}", MAX_HOLD_MINUTES)),
+                    None => Ok(Event::SetHold(Some(Hold::Indefinite))),
+                })
+        }
+        (&Method::DELETE, "/hold") => Ok(Event::SetHold(None)),
+        (&Method::POST, "/autotune") => Ok(Event::Autotune(true)),
+        (&Method::DELETE, "/autotune") => Ok(Event::Autotune(false)),
+        (&Method::PUT, "/schedule") => {
+            parse_body(request)
+                .await
+                .and_then(|entries: Vec<ScheduleEntry>| {
+                    for entry in &entries {
+                        check_target(entry.target_temperature)?;
+                    }
+                    Ok(Event::SetSchedule(Schedule::new(entries)))
+                })
+        }
+        _ => return message(StatusCode::NOT_FOUND, "Not found"),
+    };
+
+    let event = match event {
+        Ok(event) => event,
+        Err(e) => return message(StatusCode::BAD_REQUEST, &e),
+    };
+
+    match context.events.send(event).await {
+        Ok(()) => message(StatusCode::ACCEPTED, "Accepted"),
+        Err(_) => message(StatusCode::SERVICE_UNAVAILABLE, "Thermostat is not running"),
+    }
+}
+
+/// The same limits the controller enforces, but reported back to the client.
+fn check_target(target: f32) -> std::result::Result<f32, String> {
+    // Also rejects NaN
+    if TARGET_RANGE.contains(&target) {
+        Ok(target)
+    } else {
+        Err(format!(
+            "target_temperature must be {} to {}",
+            TARGET_RANGE.start(),
+            TARGET_RANGE.end()
+        ))
+    }
+}
+
+fn authorized(request: &Request<Body>, token: Option<&str>) -> bool {
+    let token = match token {
+        Some(token) => token,
+        None => return true,
+    };
+
+    let header = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    // The dashboard percent-encodes the token in the query
+    let query = request.uri().query().and_then(|query| {
+        query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("token="))
+            .and_then(percent_decode)
+    });
+
+    let matches = |given: &[u8]| constant_time_eq(given, token.as_bytes());
+    header.is_some_and(|header| matches(header.as_bytes()))
+        | query.is_some_and(|query| matches(&query))
+}
+
+/// Decode a query string value, `None` if it's malformed.
+fn percent_decode(value: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(value.len());
+    let mut bytes = value.bytes();
+
+    while let Some(byte) = bytes.next() {
+        decoded.push(match byte {
+            b'%' => {
+                let hex = [bytes.next()?, bytes.next()?];
+                u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?
+            }
+            b'+' => b' ',
+            byte => byte,
+        });
+    }
+
+    Some(decoded)
+}
+
+/// Compare without returning early, so timing doesn't give away how much of a token matched.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Server-sent events stream of every status change.
+fn status_events(status: watch::Receiver<Status>) -> Response<Body> {
+    let events = status
+        .map(|status| serde_json::to_string(&status).map(|json| format!("data: {}\n\n", json)));
+
+    Response::builder()
+        .header(CONTENT_TYPE, "text/event-stream")
+        .header(CACHE_CONTROL, "no-cache")
+        .body(Body::wrap_stream(events))
+        .unwrap_or_default()
+}
+
+async fn parse_body<T: DeserializeOwned>(request: Request<Body>) -> std::result::Result<T, String> {
+    let body = hyper::body::to_bytes(request.into_body())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    serde_json::from_slice(&body).map_err(|e| e.to_string())
+}
+
+/// Like `parse_body`, but an empty body gives the default rather than an error.
+async fn parse_body_or_default<T: DeserializeOwned + Default>(
+    request: Request<Body>,
+) -> std::result::Result<T, String> {
+    let body = hyper::body::to_bytes(request.into_body())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if body.is_empty() {
+        return Ok(T::default());
+    }
+
+    serde_json::from_slice(&body).map_err(|e| e.to_string())
+}
+
+fn json(status: StatusCode, body: &impl Serialize) -> Response<Body> {
+    match serde_json::to_vec(body) {
+        Ok(body) => Response::builder()
+            .status(status)
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .unwrap_or_default(),
+        Err(e) => message(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+fn message(status: StatusCode, message: &str) -> Response<Body> {
+    let body = serde_json::to_vec(&Message { message }).unwrap_or_default();
+
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(uri: &str) -> Request<Body> {
+        Request::builder().uri(uri).body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn accepts_an_encoded_query_token() {
+        let token = Some("a b&c/d%");
+
+        assert!(authorized(
+            &request("/events?token=a%20b%26c%2Fd%25"),
+            token
+        ));
+        assert!(authorized(&request("/events?token=a+b%26c%2fd%25"), token));
+        assert!(!authorized(&request("/events?token=a%20b"), token));
+        assert!(!authorized(&request("/events?token=%2"), token));
+        assert!(authorized(&request("/events"), None));
+    }
+}
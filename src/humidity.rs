@@ -0,0 +1,249 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+use std::io::prelude::*;
+use std::str::FromStr;
+
+/// The frost limit never goes below this, however cold it is outside.
+const MIN_LIMIT: f32 = 15.0;
+
+/// Which way the relay moves the humidity.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Equipment {
+    Humidifier,
+    Dehumidifier,
+}
+
+impl Equipment {
+    fn as_str(self) -> &'static str {
+        match self {
+            Equipment::Humidifier => "humidifier",
+            Equipment::Dehumidifier => "dehumidifier",
+        }
+    }
+}
+
+/// Humidity control, from the humidity file. Without one there's no humidity control.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub(crate) struct HumidityConfig {
+    pub(crate) equipment: Equipment,
+    /// BCM number of the GPIO driving its relay
+    pub(crate) pin: u8,
+    /// Relative humidity in percent
+    pub(crate) target: f32,
+    /// How far past the target the humidity goes before the relay comes on, it goes off again at
+    /// the target
+    pub(crate) hysteresis: f32,
+    /// Outdoor temperature below which the frost limit starts to drop
+    pub(crate) frost_from: f32,
+    /// Most humidity allowed at `frost_from` and above
+    pub(crate) frost_max: f32,
+    /// Percent lower per degree colder than `frost_from`
+    pub(crate) frost_slope: f32,
+}
+
+impl HumidityConfig {
+    /// Most humidity the windows can take at this outdoor temperature without condensation or
+    /// frost forming, `None` without an outdoor temperature.
+    pub(crate) fn limit(&self, outdoor: Option<f32>) -> Option<f32> {
+        outdoor.map(|outdoor| {
+            let colder = (self.frost_from - outdoor).max(0.0);
+            (self.frost_max - colder * self.frost_slope).max(MIN_LIMIT)
+        })
+    }
+
+    /// The target, lowered to the frost limit when it's cold out.
+    pub(crate) fn effective_target(&self, outdoor: Option<f32>) -> f32 {
+        match self.limit(outdoor) {
+            Some(limit) => self.target.min(limit),
+            None => self.target,
+        }
+    }
+
+    pub(crate) fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut file = File::open(path)?;
+        let mut contents = String::new();
+
+        file.read_to_string(&mut contents)?;
+
+        Ok(contents.parse()?)
+    }
+
+    pub(crate) fn save(&self, path: &str) -> Result<(), std::io::Error> {
+        let mut file = File::create(path)?;
+        file.write_all(self.to_string().as_bytes())
+    }
+}
+
+/// Whether a humidity target is one we'd aim for.
+pub(crate) fn valid_target(target: f32) -> bool {
+    (MIN_LIMIT..=80.0).contains(&target)
+}
+
+/// One `key value` pair per line. The pin is required, other missing keys keep their default.
+impl FromStr for HumidityConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut pin = None;
+        let mut config = HumidityConfig {
+            equipment: Equipment::Humidifier,
+            pin: 0,
+            target: 40.0,
+            hysteresis: 3.0,
+            frost_from: 40.0,
+            frost_max: 45.0,
+            frost_slope: 0.5,
+        };
+
+        for line in s.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let mut parts = line.split_whitespace();
+            let key = parts.next().unwrap_or_default();
+            let value = parts
+                .next()
+                .ok_or_else(|| format!("missing value in humidity line {:?}", line))?;
+            let invalid = || format!("invalid value in humidity line {:?}", line);
+
+            match key {
+                "equipment" => {
+                    config.equipment = match value {
+                        "humidifier" => Equipment::Humidifier,
+                        "dehumidifier" => Equipment::Dehumidifier,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "pin" => pin = Some(value.parse().map_err(|_| invalid())?),
+                "target" => config.target = value.parse().map_err(|_| invalid())?,
+                "hysteresis" => config.hysteresis = value.parse().map_err(|_| invalid())?,
+                "frost_from" => config.frost_from = value.parse().map_err(|_| invalid())?,
+                "frost_max" => config.frost_max = value.parse().map_err(|_| invalid())?,
+                "frost_slope" => config.frost_slope = value.parse().map_err(|_| invalid())?,
+                _ => return Err(format!("unknown humidity setting {:?}", key)),
+            }
+        }
+
+        config.pin = pin.ok_or("missing humidity relay pin")?;
+        if !valid_target(config.target) {
+            return Err(format!("humidity target {} out of range", config.target));
+        }
+        if config.hysteresis <= 0.0 || config.frost_slope < 0.0 {
+            return Err(
+                "humidity hysteresis must be positive and frost slope not negative".to_string(),
+            );
+        }
+
+        Ok(config)
+    }
+}
+
+impl fmt::Display for HumidityConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "equipment {}", self.equipment.as_str())?;
+        writeln!(f, "pin {}", self.pin)?;
+        writeln!(f, "target {}", self.target)?;
+        writeln!(f, "hysteresis {}", self.hysteresis)?;
+        writeln!(f, "frost_from {}", self.frost_from)?;
+        writeln!(f, "frost_max {}", self.frost_max)?;
+        writeln!(f, "frost_slope {}", self.frost_slope)
+    }
+}
+
+/// Switches a humidifier or dehumidifier relay around the target.
+#[derive(Debug)]
+pub(crate) struct Humidistat {
+    pub(crate) config: HumidityConfig,
+    on: bool,
+}
+
+impl Humidistat {
+    pub(crate) fn new(config: HumidityConfig) -> Self {
+        Self { config, on: false }
+    }
+
+    pub(crate) fn on(&self) -> bool {
+        self.on
+    }
+
+    /// Work out the relay from a reading, or `None` while the sensor is failing, returning
+    /// whether it changed.
+    pub(crate) fn update(&mut self, humidity: Option<f32>, outdoor: Option<f32>) -> bool {
+        let target = self.config.effective_target(outdoor);
+        let hysteresis = self.config.hysteresis;
+
+        let on = match (humidity, self.config.equipment) {
+            (None, _) => false,
+            (Some(humidity), Equipment::Humidifier) if self.on => humidity < target,
+            (Some(humidity), Equipment::Humidifier) => humidity < target - hysteresis,
+            (Some(humidity), Equipment::Dehumidifier) if self.on => humidity > target,
+            (Some(humidity), Equipment::Dehumidifier) => humidity > target + hysteresis,
+        };
+
+        let changed = on != self.on;
+        self.on = on;
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> HumidityConfig {
+        "pin 22\ntarget 40\n".parse().unwrap()
+    }
+
+    #[test]
+    fn round_trips_through_text() {
+        let config = HumidityConfig {
+            equipment: Equipment::Dehumidifier,
+            frost_slope: 1.0,
+            ..config()
+        };
+
+        assert_eq!(config.to_string().parse(), Ok(config));
+        assert!("target 40\n".parse::<HumidityConfig>().is_err());
+        assert!("pin 22\ntarget 95\n".parse::<HumidityConfig>().is_err());
+        assert!("pin 22\nequipment mister\n"
+            .parse::<HumidityConfig>()
+            .is_err());
+    }
+
+    #[test]
+    fn frost_limit_lowers_the_target() {
+        let config = config();
+
+        assert_eq!(config.effective_target(None), 40.0);
+        assert_eq!(config.effective_target(Some(50.0)), 40.0);
+        assert_eq!(config.effective_target(Some(20.0)), 35.0);
+        assert_eq!(config.effective_target(Some(-40.0)), MIN_LIMIT);
+    }
+
+    #[test]
+    fn switches_around_the_target() {
+        let mut humidistat = Humidistat::new(config());
+
+        assert!(!humidistat.update(Some(38.0), None));
+        assert!(humidistat.update(Some(36.0), None));
+        assert!(!humidistat.update(Some(39.0), None));
+        assert!(humidistat.update(Some(40.0), None));
+        assert!(!humidistat.on());
+
+        // Colder outside brings the target down to where it already is
+        humidistat.update(Some(36.0), None);
+        assert!(humidistat.update(Some(36.0), Some(10.0)));
+        assert!(!humidistat.on());
+
+        // And a failing sensor switches it off
+        humidistat.update(Some(30.0), None);
+        assert!(humidistat.update(None, None));
+
+        let mut dehumidifier = Humidistat::new(HumidityConfig {
+            equipment: Equipment::Dehumidifier,
+            ..config()
+        });
+        assert!(dehumidifier.update(Some(44.0), None));
+        assert!(!dehumidifier.update(Some(41.0), None));
+        assert!(dehumidifier.update(Some(40.0), None));
+    }
+}
@@ -0,0 +1,97 @@
+use crate::error::{Error, Result};
+use std::env;
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{info, warn};
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{fmt, reload, EnvFilter, Registry};
+
+/// Log filter used when `THERMOSTAT_LOG` isn't set, uses `EnvFilter` directive syntax.
+const DEFAULT_FILTER: &str = "info";
+/// Filter switched to by `SIGUSR1`, `SIGUSR2` switches back to the configured one.
+const VERBOSE_FILTER: &str = "debug";
+
+/// Handle for changing the log filter while running.
+#[derive(Debug, Clone)]
+pub(crate) struct LogHandle {
+    handle: reload::Handle<EnvFilter, Registry>,
+    configured: String,
+}
+
+impl LogHandle {
+    pub(crate) fn set_filter(&self, directives: &str) -> Result<()> {
+        let filter = EnvFilter::try_new(directives.trim())
+            .map_err(|e| Error::Config(format!("invalid log filter {:?}: {}", directives, e)))?;
+        self.handle
+            .reload(filter)
+            .map_err(|e| Error::Config(format!("failed to reload log filter: {}", e)))?;
+
+        info!(filter = directives.trim(), "Log filter changed");
+
+        Ok(())
+    }
+
+    pub(crate) fn reset(&self) -> Result<()> {
+        self.set_filter(&self.configured)
+    }
+}
+
+/// Install the global subscriber.
+///
+/// Verbosity comes from `THERMOSTAT_LOG` and output format from `THERMOSTAT_LOG_FORMAT`, which is
+/// one of `text` (the default), `json` or `journald`.
+pub(crate) fn init() -> LogHandle {
+    let configured = env::var("THERMOSTAT_LOG").unwrap_or_else(|_| DEFAULT_FILTER.to_string());
+    let (filter, invalid) = match EnvFilter::try_new(&configured) {
+        Ok(filter) => (filter, false),
+        Err(_) => (EnvFilter::new(DEFAULT_FILTER), true),
+    };
+    let (filter, handle) = reload::Layer::new(filter);
+    let registry = tracing_subscriber::registry().with(filter);
+
+    let format = env::var("THERMOSTAT_LOG_FORMAT").unwrap_or_default();
+    match format.as_str() {
+        "json" => registry.with(fmt::layer().json()).init(),
+        "journald" => match tracing_journald::layer() {
+            Ok(journald) => registry.with(journald).init(),
+            Err(e) => {
+                registry.with(fmt::layer()).init();
+                warn!(error = %e, "Couldn't connect to journald, logging to stdout");
+            }
+        },
+        "" | "text" => registry.with(fmt::layer()).init(),
+        other => {
+            registry.with(fmt::layer()).init();
+            warn!(format = other, "Unknown log format, using text");
+        }
+    }
+
+    if invalid {
+        warn!(filter = %configured, "Invalid THERMOSTAT_LOG filter, using default");
+    }
+
+    LogHandle {
+        handle,
+        configured: if invalid {
+            DEFAULT_FILTER.to_string()
+        } else {
+            configured
+        },
+    }
+}
+
+/// Switch to verbose logging on `SIGUSR1` and back to the configured filter on `SIGUSR2`.
+pub(crate) async fn handle_signals(log: LogHandle) -> Result<()> {
+    let mut verbose = signal(SignalKind::user_defined1())?;
+    let mut normal = signal(SignalKind::user_defined2())?;
+
+    loop {
+        let result = tokio::select! {
+            _ = verbose.recv() => log.set_filter(VERBOSE_FILTER),
+            _ = normal.recv() => log.reset(),
+        };
+
+        if let Err(e) = result {
+            warn!(error = %e, "Failed to change log filter");
+        }
+    }
+}
@@ -1,34 +1,113 @@
 #![warn(missing_debug_implementations)]
 
-use std::error::Error;
+use std::env;
 use std::fs::File;
 use std::io::prelude::*;
-use std::str;
+use std::ops::RangeInclusive;
+use std::str::{self, FromStr};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use chrono::Timelike;
-use rppal::gpio::{Gpio, IoPin, Mode, OutputPin};
-use rumq_client::{Notification, Publish, QoS, Request};
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Timelike};
+use rppal::gpio::{Gpio, IoPin, Mode as PinMode, OutputPin};
+use rumq_client::Notification;
+use serde::{Deserialize, Serialize};
 use tokio::stream::StreamExt;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
-use tokio::time::{delay_for, timeout};
+use tokio::sync::{watch, Mutex};
+use tokio::time::{delay_for, delay_until};
+use tracing::{debug, error, info, info_span, warn, Instrument};
 
+mod anticipator;
+mod autotune;
 mod buttons;
 mod client;
+mod control;
 mod dht;
 mod display;
+mod error;
+mod history;
+mod http;
+mod humidity;
+mod logging;
+mod menu;
+mod metrics;
+mod offline;
+mod publisher;
+mod recovery;
+mod schedule;
+mod settings;
+mod staging;
+mod supervisor;
 
+use anticipator::Anticipator;
+use autotune::Autotune;
+use buttons::{ButtonEvent, EditorAction, TargetEditor};
+use control::{Algorithm, ControlConfig, DutyCycle, Pid};
 use display::Display;
+use error::Error;
+use history::History;
+use humidity::{Humidistat, HumidityConfig};
+use menu::{Menu, MenuAction, MenuContext};
+use metrics::Metrics;
+use publisher::Publisher;
+use recovery::RecoveryModel;
+use schedule::{Hold, Schedule};
+use settings::Settings;
+use staging::{Stager, StagingConfig};
+use supervisor::supervise;
 
 const SENSOR_PIN: u8 = 16;
 const RELAY_PIN: u8 = 4;
 const UP_BUTTON_PIN: u8 = 7;
 const DOWN_BUTTON_PIN: u8 = 8;
+// Units with THERMOSTAT_INPUT=encoder use a rotary encoder in place of the buttons
+const ENCODER_A_PIN: u8 = 7;
+const ENCODER_B_PIN: u8 = 8;
+const ENCODER_SWITCH_PIN: u8 = 25;
+// Quadrature steps between the encoder's clicks
+const ENCODER_STEPS_PER_DETENT: u8 = 4;
+// Each button press after the first changes the target by this much
+const BUTTON_INCREMENT: f32 = 0.5;
+// Button edits are applied once the buttons have been left alone this long
+const BUTTON_COMMIT_DELAY: Duration = Duration::from_secs(3);
+const BUTTON_TIMINGS: buttons::Timings = buttons::Timings {
+    debounce: Duration::from_millis(30),
+    long_press: Duration::from_millis(800),
+    repeat_interval: Duration::from_millis(200),
+    combo_window: Duration::from_millis(250),
+};
 const DEFAULT_TARGET: f32 = 70.0;
+// Targets outside this are a typo or a bad client, not something to heat to
+const TARGET_RANGE: RangeInclusive<f32> = 40.0..=90.0;
 const SAVE_FILE: &str = "target.txt";
+const MODE_FILE: &str = "mode.txt";
+const SCHEDULE_FILE: &str = "schedule.txt";
+const SETTINGS_FILE: &str = "settings.txt";
+// Control algorithm and PID tuning
+const CONTROL_FILE: &str = "control.txt";
+// How early the hysteresis control learned to switch, kept across restarts
+const ANTICIPATOR_FILE: &str = "anticipator.txt";
+// How quickly the room has warmed up before, for starting early on a schedule
+const RECOVERY_FILE: &str = "recovery.txt";
+// Further heating stages on their own relays
+const STAGING_FILE: &str = "staging.txt";
+// Humidifier or dehumidifier, there's no humidity control without it
+const HUMIDITY_FILE: &str = "humidity.txt";
+// The on-device menu goes back to the status screen after this long without a press
+const MENU_TIMEOUT: Duration = Duration::from_secs(30);
+// Alarm when there's been no good sensor reading for this long
+const SENSOR_STALE_AFTER: Duration = Duration::from_secs(60);
+// Alarm when the room gets cold enough for pipes to be at risk
+const FROST_ALARM: f32 = 45.0;
 const VARIANCE: f32 = 1.0;
 const I2CDEVICE: &str = "/dev/i2c-1";
 const LCDBUS: u16 = 0x27;
+// For units with THERMOSTAT_DISPLAY=oled
+const OLED_I2C_BUS: u8 = 1;
+const OLED_ADDRESS: u16 = 0x3C;
+// Can be overridden with THERMOSTAT_HTTP_ADDRESS
+const HTTP_ADDRESS: &str = "0.0.0.0:9100";
 
 const MQTT_HOST: &str = "192.168.1.25:1883";
 const TEMPERATURE_TOPIC: &str = "bedroom/heat/current_temperature/get";
@@ -36,17 +115,84 @@ const HUMIDITY_TOPIC: &str = "bedroom/heat/current_humidity/get";
 const SET_TARGET_TOPIC: &str = "bedroom/heat/target_temperature/set";
 const GET_TARGET_TOPIC: &str = "bedroom/heat/target_temperature/get";
 const MODE_TOPIC: &str = "bedroom/heat/mode/state";
+// What the heating relay is doing, as heating, idle or off
+const ACTION_TOPIC: &str = "bedroom/heat/action/state";
 const DESK_TEMPERATURE_TOPIC: &str = "desk/current_temperature/get";
+const OUTDOOR_TEMPERATURE_TOPIC: &str = "outdoor/current_temperature/get";
+const LOG_FILTER_TOPIC: &str = "bedroom/heat/log_filter/set";
+// Backlight as a light entity, with ON/OFF payloads
+const BACKLIGHT_SET_TOPIC: &str = "bedroom/heat/backlight/set";
+const BACKLIGHT_STATE_TOPIC: &str = "bedroom/heat/backlight/state";
+// Whether the display is working, ONLINE or OFFLINE
+const DISPLAY_STATE_TOPIC: &str = "bedroom/heat/display/state";
+// JSON terms of each PID cycle, for tuning
+const CONTROLLER_STATE_TOPIC: &str = "bedroom/heat/controller/state";
+// Active alarms separated by "; ", or "none"
+const ALARM_TOPIC: &str = "bedroom/heat/alarm/state";
+// When heating starts early for the next scheduled rise, as an RFC 3339 time or "none"
+const OPTIMAL_START_TOPIC: &str = "bedroom/heat/optimal_start/state";
+// How many heating stages are running, 0 when the heating's off
+const STAGES_TOPIC: &str = "bedroom/heat/stages/state";
+const SET_TARGET_HUMIDITY_TOPIC: &str = "bedroom/humidity/target_humidity/set";
+const GET_TARGET_HUMIDITY_TOPIC: &str = "bedroom/humidity/target_humidity/get";
+// What the humidity relay is doing, as humidifying, drying or idle
+const HUMIDITY_ACTION_TOPIC: &str = "bedroom/humidity/action/state";
 const MAX_TEMPERATURE_LAG: Duration = Duration::from_secs(60 * 10);
+// Outdoor sensors report less often, but after this long the reading is ignored
+const OUTDOOR_STALE_AFTER: Duration = Duration::from_secs(60 * 30);
+// Minimum change before a new reading is published
+const TEMPERATURE_DEADBAND: f32 = 0.1;
+const HUMIDITY_DEADBAND: f32 = 1.0;
+// Unchanged values are still republished this often so subscribers know we're alive
+const PUBLISH_HEARTBEAT: Duration = Duration::from_secs(60 * 5);
+// Telemetry recorded while the broker is unreachable, replayed to REPLAY_TOPIC on reconnect
+const OFFLINE_QUEUE_FILE: &str = "offline_queue.txt";
+const OFFLINE_QUEUE_CAPACITY: usize = 10_000;
+const REPLAY_TOPIC: &str = "bedroom/heat/telemetry/replay";
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Status {
     temperature: f32,
     humidity: f32,
     target_temperature: f32,
     running: bool,
     desk_temperature: f32,
+    #[serde(skip)]
     desk_temperature_updated: Instant,
+    /// From MQTT, `None` until one arrives or once it's stale
+    outdoor_temperature: Option<f32>,
+    /// `None` until an outdoor temperature arrives
+    #[serde(skip)]
+    outdoor_temperature_updated: Option<Instant>,
+    /// Added to the target for control by the heating curve, as it's cold outside
+    curve_offset: f32,
+    /// Relays of the heating stages after the first, in order
+    stages: Vec<bool>,
+    /// Auxiliary stages aren't used as it's mild outside
+    aux_locked_out: bool,
+    /// `None` without humidity control
+    humidity_control: Option<HumidityConfig>,
+    /// Most humidity allowed at the outdoor temperature, `None` when it's unknown
+    humidity_limit: Option<f32>,
+    humidity_relay: bool,
+    mode: Mode,
+    hold: Option<Hold>,
+    schedule: Schedule,
+    /// When heating starts, or started, early to reach the next scheduled target on time
+    optimal_start: Option<DateTime<Local>>,
+    settings: Settings,
+    control: ControlConfig,
+    /// A PID autotune experiment is driving the relay
+    autotuning: bool,
+    backlight: bool,
+    /// The display is working, it's retried in the background when it isn't
+    display_healthy: bool,
+    /// No recent reading, `temperature` is stale or was never read
+    sensor_fault: bool,
+    /// `None` when MQTT isn't set up
+    mqtt_connected: Option<bool>,
+    /// Active alarms, as on the alarms page
+    alarms: Vec<String>,
 }
 
 impl Status {
@@ -59,6 +205,54 @@ impl Status {
             desk_temperature: 0.0,
             // Start with out of date temperature so it's ignored
             desk_temperature_updated: Instant::now() - MAX_TEMPERATURE_LAG,
+            outdoor_temperature: None,
+            outdoor_temperature_updated: None,
+            curve_offset: 0.0,
+            stages: Vec::new(),
+            aux_locked_out: false,
+            humidity_control: None,
+            humidity_limit: None,
+            humidity_relay: false,
+            mode: Mode::Heat,
+            hold: None,
+            schedule: Schedule::default(),
+            optimal_start: None,
+            settings: Settings::default(),
+            control: ControlConfig::default(),
+            autotuning: false,
+            backlight: false,
+            display_healthy: true,
+            sensor_fault: true,
+            mqtt_connected: None,
+            alarms: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Mode {
+    Heat,
+    Off,
+}
+
+impl Mode {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Mode::Heat => "heat",
+            Mode::Off => "off",
+        }
+    }
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "heat" => Ok(Mode::Heat),
+            "off" => Ok(Mode::Off),
+            other => Err(format!("unknown mode {:?}", other)),
         }
     }
 }
@@ -67,147 +261,1125 @@ impl Status {
 enum Event {
     UpdateTarget(f32),
     UpdateDeskTemperature(f32),
-    Reading { temperature: f32, humidity: f32 },
+    UpdateOutdoorTemperature(f32),
+    UpdateTargetHumidity(f32),
+    Reading {
+        temperature: f32,
+        humidity: f32,
+    },
+    SetMode(Mode),
+    /// Hold the current target, or `None` to go back to following the schedule
+    SetHold(Option<Hold>),
+    SetSchedule(Schedule),
+    UpdateSettings(Settings),
+    /// Force the backlight on, or back to automatic when off
+    SetBacklight(bool),
+    /// The display switched the backlight
+    BacklightChanged(bool),
+    /// The display stopped working, or recovered
+    DisplayHealth(bool),
+    /// A sensor read failed, there may be new alarms
+    SensorFailed,
+    Button(ButtonEvent),
+    /// A button editing timer may have expired
+    ButtonTimer,
+    /// The relay may need switching part way through a control cycle
+    ControlTimer,
+    /// Start an experiment to find the PID gains, or cancel it when false
+    Autotune(bool),
 }
 
 #[tokio::main(basic_scheduler)]
-async fn main() -> Result<(), Box<dyn Error>> {
+async fn main() -> error::Result<()> {
+    let log = logging::init();
+    {
+        let log = log.clone();
+        supervise("log signals", move || logging::handle_signals(log.clone()));
+    }
+
     let gpio = Gpio::new()?;
-    let mut pin = gpio.get(SENSOR_PIN)?.into_io(Mode::Input);
+    let mut pin = gpio.get(SENSOR_PIN)?.into_io(PinMode::Input);
     let relay_pin = gpio.get(RELAY_PIN)?.into_output();
 
-    let display = display::Display::new(I2CDEVICE, LCDBUS)?;
+    let mut staging = initial_staging();
+    let mut stage_pins = Vec::new();
+    // A bad pin in the staging file loses that stage rather than all heating
+    staging.stages.retain(|stage| match gpio.get(stage.pin) {
+        Ok(pin) => {
+            let mut pin = pin.into_output();
+            pin.set_low();
+            stage_pins.push(pin);
+            true
+        }
+        Err(e) => {
+            error!(error = %e, pin = stage.pin, "Heating stage unavailable");
+            false
+        }
+    });
+
+    let (humidistat, humidity_pin) = match initial_humidity() {
+        Some(config) => match gpio.get(config.pin) {
+            Ok(pin) => {
+                let mut pin = pin.into_output();
+                pin.set_low();
+                (Some(Humidistat::new(config)), Some(pin))
+            }
+            Err(e) => {
+                error!(error = %e, pin = config.pin, "Humidity relay unavailable");
+                (None, None)
+            }
+        },
+        None => (None, None),
+    };
 
-    let _button_handler =
-        buttons::ButtonHandler::new(&gpio, UP_BUTTON_PIN, DOWN_BUTTON_PIN, display.clone())?;
+    let (events_tx, events_rx) = channel(50);
 
-    let status = Status::new(initial_target(), relay_pin.is_set_high());
+    // THERMOSTAT_DISPLAY picks the panel, or terminal to draw the screen on stdout
+    let display = match env::var("THERMOSTAT_DISPLAY").as_deref() {
+        Ok("terminal") => display::Display::terminal(events_tx.clone()),
+        Ok("lcd16x2") => display::Display::compact(I2CDEVICE, LCDBUS, events_tx.clone()),
+        Ok("oled") => display::Display::oled(OLED_I2C_BUS, OLED_ADDRESS, events_tx.clone()),
+        _ => display::Display::new(I2CDEVICE, LCDBUS, events_tx.clone()),
+    };
 
-    let (requests_tx, notifications_rx) =
-        client::connect(MQTT_HOST, vec![SET_TARGET_TOPIC, DESK_TEMPERATURE_TOPIC]).await;
+    let mut status = Status::new(initial_target(), relay_pin.is_set_high());
+    status.mode = initial_mode();
+    status.schedule = initial_schedule();
+    status.settings = initial_settings();
+    status.control = initial_control();
+    status.stages = vec![false; staging.stages.len()];
+    status.humidity_control = humidistat.as_ref().map(|humidistat| humidistat.config);
+    let (status_tx, status_rx) = watch::channel(status.clone());
 
-    let (events_tx, events_rx) = channel(50);
+    // Kept around so the pin interrupts stay registered
+    let (_button_handler, _encoder_handler) = match env::var("THERMOSTAT_INPUT").as_deref() {
+        Ok("encoder") => {
+            let encoder = buttons::EncoderHandler::new(
+                &gpio,
+                ENCODER_A_PIN,
+                ENCODER_B_PIN,
+                ENCODER_SWITCH_PIN,
+                ENCODER_STEPS_PER_DETENT,
+                BUTTON_TIMINGS,
+                events_tx.clone(),
+            )?;
+            (None, Some(encoder))
+        }
+        _ => {
+            let buttons = buttons::ButtonHandler::new(
+                &gpio,
+                UP_BUTTON_PIN,
+                DOWN_BUTTON_PIN,
+                BUTTON_TIMINGS,
+                events_tx.clone(),
+            )?;
+            (Some(buttons), None)
+        }
+    };
+
+    let metrics = Metrics::default();
+    let history = History::default();
+    let http_address = env::var("THERMOSTAT_HTTP_ADDRESS").unwrap_or_else(|_| HTTP_ADDRESS.into());
+    match http_address.parse() {
+        Ok(address) => {
+            let context = http::Context {
+                metrics: metrics.clone(),
+                history: history.clone(),
+                status: status_rx,
+                events: events_tx.clone(),
+                token: env::var("THERMOSTAT_API_TOKEN").ok(),
+            };
+            supervise("http", move || {
+                http::serve(address, context.clone()).instrument(info_span!("http"))
+            });
+        }
+        Err(e) => error!(error = %e, address = %http_address, "Invalid HTTP address"),
+    }
 
-    tokio::task::spawn(process_mqtt_stream(notifications_rx, events_tx.clone()));
+    // Without a working MQTT setup we still want to keep the room warm, so carry on local-only
+    let (publisher, mqtt_connected) = match client::connect(
+        MQTT_HOST,
+        vec![
+            SET_TARGET_TOPIC,
+            DESK_TEMPERATURE_TOPIC,
+            OUTDOOR_TEMPERATURE_TOPIC,
+            SET_TARGET_HUMIDITY_TOPIC,
+            LOG_FILTER_TOPIC,
+            BACKLIGHT_SET_TOPIC,
+        ],
+        metrics.clone(),
+    )
+    .await
+    {
+        Ok((requests_tx, notifications_rx, connected_rx)) => {
+            let notifications_rx = Arc::new(Mutex::new(notifications_rx));
+            let events_tx = events_tx.clone();
+            supervise("mqtt stream", move || {
+                process_mqtt_stream(notifications_rx.clone(), events_tx.clone(), log.clone())
+                    .instrument(info_span!("mqtt"))
+            });
 
-    tokio::task::spawn(process_events(
-        events_rx,
-        requests_tx,
+            (
+                Some(Publisher::new(
+                    requests_tx,
+                    connected_rx.clone(),
+                    metrics.clone(),
+                )),
+                Some(connected_rx),
+            )
+        }
+        Err(e) => {
+            error!(error = %e, "MQTT unavailable, running in local-only mode");
+            (None, None)
+        }
+    };
+
+    let events_rx = Arc::new(Mutex::new(events_rx));
+    let controller = Arc::new(Mutex::new(Controller {
+        // Treat whatever the schedule currently says as already applied so a restart doesn't
+        // clobber a target that was set by hand
+        schedule_entry: status
+            .schedule
+            .current(Local::now().naive_local())
+            .map(|(start, _)| start),
         status,
+        status_tx,
         display,
         relay_pin,
-    ));
-
-    poll_sensor(events_tx, &mut pin).await;
+        staging,
+        stage_pins,
+        stager: Stager::default(),
+        humidistat,
+        humidity_pin,
+        publisher,
+        metrics: metrics.clone(),
+        history,
+        editor: TargetEditor::new(BUTTON_INCREMENT, BUTTON_COMMIT_DELAY),
+        menu: None,
+        mqtt_connected,
+        http_address,
+        last_reading: None,
+        alarms: Vec::new(),
+        pid: Pid::default(),
+        duty_cycle: DutyCycle::default(),
+        autotune: None,
+        anticipator: initial_anticipator(),
+        recovery: initial_recovery(),
+        events_tx: events_tx.clone(),
+    }));
+    supervise("event processing", move || {
+        process_events(events_rx.clone(), controller.clone()).instrument(info_span!("control"))
+    });
 
-    Ok(())
+    poll_sensor(events_tx, &mut pin, metrics)
+        .instrument(info_span!("sensor"))
+        .await
 }
 
 async fn process_mqtt_stream(
-    mut notifications_rx: Receiver<Notification>,
+    notifications_rx: Arc<Mutex<Receiver<Notification>>>,
     mut events_tx: Sender<Event>,
-) {
+    log: logging::LogHandle,
+) -> error::Result<()> {
+    let mut notifications_rx = notifications_rx.lock().await;
+
     while let Some(notification) = notifications_rx.next().await {
-        match notification {
+        let event = match notification {
             Notification::Publish(message) => match message.topic_name.as_ref() {
-                SET_TARGET_TOPIC => {
-                    if let Ok(Ok(new_target)) = str::from_utf8(&message.payload).map(|t| t.parse())
-                    {
-                        events_tx
-                            .send(Event::UpdateTarget(new_target))
-                            .await
-                            .unwrap();
+                SET_TARGET_TOPIC => str::from_utf8(&message.payload)
+                    .ok()
+                    .and_then(|t| t.parse().ok())
+                    .map(Event::UpdateTarget),
+                DESK_TEMPERATURE_TOPIC => str::from_utf8(&message.payload)
+                    .ok()
+                    .and_then(|t| t.parse().ok())
+                    .map(Event::UpdateDeskTemperature),
+                // "NaN" parses, and would stop anything comparing against it from switching
+                OUTDOOR_TEMPERATURE_TOPIC => str::from_utf8(&message.payload)
+                    .ok()
+                    .and_then(|t| t.parse().ok())
+                    .filter(|t: &f32| t.is_finite())
+                    .map(Event::UpdateOutdoorTemperature),
+                SET_TARGET_HUMIDITY_TOPIC => str::from_utf8(&message.payload)
+                    .ok()
+                    .and_then(|t| t.parse().ok())
+                    .map(Event::UpdateTargetHumidity),
+                BACKLIGHT_SET_TOPIC => match str::from_utf8(&message.payload) {
+                    Ok("ON") => Some(Event::SetBacklight(true)),
+                    Ok("OFF") => Some(Event::SetBacklight(false)),
+                    _ => {
+                        warn!(payload = ?message.payload, "Invalid backlight command");
+                        None
                     }
-                }
-                DESK_TEMPERATURE_TOPIC => {
-                    if let Ok(Ok(new_temperature)) =
-                        str::from_utf8(&message.payload).map(|t| t.parse())
-                    {
-                        events_tx
-                            .send(Event::UpdateDeskTemperature(new_temperature))
-                            .await
-                            .unwrap();
+                },
+                LOG_FILTER_TOPIC => {
+                    match str::from_utf8(&message.payload) {
+                        Ok(filter) => {
+                            if let Err(e) = log.set_filter(filter) {
+                                warn!(error = %e, "Rejected log filter");
+                            }
+                        }
+                        Err(e) => warn!(error = %e, "Log filter isn't valid UTF-8"),
                     }
+                    None
+                }
+                _ => {
+                    warn!(topic = %message.topic_name, "Unrecognized topic event");
+                    None
                 }
-                _ => eprintln!("Unrecognized topic event: {:?}", message),
             },
             // Every publish we do gets an ack but that's not something we care about,
             // ignore these completely
-            Notification::Puback(_) => {}
+            Notification::Puback(_) => None,
             _ => {
-                eprintln!("Unhandled event {:?}", notification);
+                debug!(?notification, "Unhandled event");
+                None
             }
+        };
+
+        if let Some(event) = event {
+            events_tx
+                .send(event)
+                .await
+                .map_err(|_| Error::ChannelClosed("events"))?;
         }
     }
+
+    Ok(())
 }
 
-async fn process_events(
-    mut events_rx: Receiver<Event>,
-    requests_tx: Sender<Request>,
-    mut status: Status,
+/// State owned by the event loop.
+///
+/// Kept behind a mutex rather than moved into the task so that if event processing is restarted
+/// the new run picks up the same relay pin and status.
+#[derive(Debug)]
+struct Controller {
+    status: Status,
+    status_tx: watch::Sender<Status>,
+    /// Start of the schedule entry whose target was last applied
+    schedule_entry: Option<NaiveDateTime>,
     display: Display,
-    mut relay_pin: OutputPin,
-) {
-    while let Some(event) = events_rx.next().await {
+    relay_pin: OutputPin,
+    staging: StagingConfig,
+    /// Relays of the heating stages after the first, in the staging file's order
+    stage_pins: Vec<OutputPin>,
+    stager: Stager,
+    /// Both `None` without humidity control
+    humidistat: Option<Humidistat>,
+    humidity_pin: Option<OutputPin>,
+    publisher: Option<Publisher>,
+    metrics: Metrics,
+    history: History,
+    editor: TargetEditor,
+    /// The on-device menu, when it's open
+    menu: Option<Menu>,
+    /// `None` when running without MQTT
+    mqtt_connected: Option<watch::Receiver<bool>>,
+    http_address: String,
+    last_reading: Option<Instant>,
+    /// Alarms as of the last check, to spot new ones
+    alarms: Vec<String>,
+    pid: Pid,
+    duty_cycle: DutyCycle,
+    autotune: Option<Autotune>,
+    anticipator: Anticipator,
+    recovery: RecoveryModel,
+    /// For scheduling our own timer events
+    events_tx: Sender<Event>,
+}
+
+impl Controller {
+    fn handle_event(&mut self, event: Event) {
         match event {
-            Event::UpdateTarget(new_target) => {
-                status.target_temperature = new_target;
+            Event::UpdateTarget(new_target) => self.set_target(new_target),
+            Event::Reading {
+                temperature,
+                humidity,
+            } => {
+                self.status.temperature = temperature + self.status.settings.calibration_offset;
+                self.status.humidity = humidity;
+                self.last_reading = Some(Instant::now());
+
+                self.expire_outdoor_temperature();
+                self.apply_schedule();
+
+                self.drive_relay();
+                self.drive_humidity();
+                self.learn_recovery();
+
+                let status = &self.status;
+                debug!(
+                    temperature = status.temperature,
+                    effective_temperature = effective_temperature(status),
+                    humidity = status.humidity,
+                    target_temperature = status.target_temperature,
+                    curve_offset = status.curve_offset,
+                    running = status.running,
+                    "Reading"
+                );
+
+                self.status_changed();
+            }
+            Event::UpdateDeskTemperature(desk_temperature) => {
+                debug!(desk_temperature, "New desk temperature");
+
+                self.status.desk_temperature = desk_temperature;
+                self.status.desk_temperature_updated = Instant::now();
+            }
+            Event::UpdateOutdoorTemperature(outdoor_temperature) => {
+                debug!(outdoor_temperature, "New outdoor temperature");
+
+                self.status.outdoor_temperature = Some(outdoor_temperature);
+                self.status.outdoor_temperature_updated = Some(Instant::now());
+                self.update_curve_offset();
+            }
+            Event::UpdateTargetHumidity(target) => self.set_target_humidity(target),
+            Event::SetMode(mode) => {
+                self.status.mode = mode;
+
+                if let Err(e) = write_mode_to_file(mode) {
+                    error!(error = %e, "Failed to persist mode");
+                }
+
+                info!(mode = mode.as_str(), "New mode");
+
+                self.stop_autotune("mode changed");
+                self.drive_relay();
 
-                if let Err(e) = write_target_to_file(new_target) {
-                    eprintln!("Failed to persist target, got ({})", e);
+                self.status_changed();
+            }
+            Event::SetHold(hold) => {
+                info!(?hold, "New hold");
+                self.status.hold = hold;
+
+                if hold.is_none() {
+                    // Resuming, pick up whatever the schedule says right now
+                    self.schedule_entry = None;
+                    self.apply_schedule();
+                }
+
+                self.status_changed();
+            }
+            Event::SetSchedule(schedule) => {
+                if let Err(e) = schedule.save(SCHEDULE_FILE) {
+                    error!(error = %e, "Failed to persist schedule");
                 }
 
-                if let Err(e) = display.update_status(&status) {
-                    eprintln!("LCD Error: {:?}", e);
-                };
+                info!(entries = schedule.entries().len(), "New schedule");
+                self.status.schedule = schedule;
+                self.schedule_entry = None;
+                self.apply_schedule();
+
+                self.status_changed();
+            }
+            Event::UpdateSettings(settings) => {
+                if let Err(e) = settings.save(SETTINGS_FILE) {
+                    error!(error = %e, "Failed to persist settings");
+                }
+
+                info!(?settings, "New settings");
+                self.status.settings = settings;
+
+                self.status_changed();
+            }
+            Event::SetBacklight(on) => {
+                if let Err(e) = self.display.set_backlight(on) {
+                    error!(error = %e, "LCD error");
+                }
+            }
+            Event::BacklightChanged(on) => {
+                debug!(on, "Backlight changed");
+                self.status.backlight = on;
+
+                self.status_changed();
+            }
+            Event::DisplayHealth(healthy) => {
+                if healthy {
+                    info!("Display working");
+                } else {
+                    warn!("Display failing");
+                }
+                self.status.display_healthy = healthy;
+
+                self.status_changed();
+            }
+            Event::SensorFailed => {
+                if self.sensor_fault() {
+                    self.stop_autotune("no sensor reading");
+                }
+                let relays = self.drive_relay();
+                let humidity = self.drive_humidity();
+                if relays || humidity || self.sensor_fault() != self.status.sensor_fault {
+                    self.status_changed();
+                } else {
+                    self.check_alarms();
+                }
+            }
+            Event::Button(event) => {
+                self.handle_button(event);
+                self.schedule_button_timer();
+            }
+            Event::ButtonTimer => {
+                let now = Instant::now();
 
-                println!("New target: {}", new_target);
+                if let Some(action) = self.editor.poll(now) {
+                    self.apply_editor_action(action);
+                }
+                if matches!(&self.menu, Some(menu) if now >= menu.deadline()) {
+                    self.close_menu();
+                }
+                self.schedule_button_timer();
+            }
+            Event::ControlTimer => {
+                if self.drive_relay() {
+                    self.status_changed();
+                }
+            }
+            Event::Autotune(true) => {
+                if self.autotune.is_some() {
+                    return;
+                }
+                if self.status.mode == Mode::Off || self.sensor_fault() {
+                    warn!("Autotune needs heating on and a working sensor");
+                    return;
+                }
 
-                mqtt_publish(
-                    requests_tx.clone(),
-                    GET_TARGET_TOPIC,
-                    &status.target_temperature.to_string(),
+                info!(
+                    target_temperature = self.status.target_temperature,
+                    "Autotune started"
                 );
+                self.autotune = Some(Autotune::new(
+                    self.status.target_temperature,
+                    Instant::now(),
+                ));
+                self.status.autotuning = true;
+                self.drive_relay();
+
+                self.status_changed();
+            }
+            Event::Autotune(false) => {
+                self.stop_autotune("cancelled");
+
+                self.status_changed();
+            }
+        }
+    }
+
+    fn handle_button(&mut self, event: ButtonEvent) {
+        let now = Instant::now();
+
+        // A press with the backlight off only lights the screen
+        let lit = self.status.backlight;
+        if let Err(e) = self.display.wake() {
+            error!(error = %e, "LCD error");
+        }
+        if !lit {
+            return;
+        }
+
+        let mut menu = match self.menu.take() {
+            Some(menu) => menu,
+            None => {
+                match event {
+                    ButtonEvent::Short(button) => {
+                        let action = self
+                            .editor
+                            .press(button, self.status.target_temperature, now);
+                        self.apply_editor_action(action);
+                    }
+                    ButtonEvent::Long(_) | ButtonEvent::Combo | ButtonEvent::Select => {
+                        self.open_menu(now)
+                    }
+                    ButtonEvent::Repeat(_) => {}
+                }
+                return;
+            }
+        };
+
+        let action = menu.handle(event, &self.menu_context(), now);
+        self.menu = Some(menu);
+
+        match action {
+            MenuAction::Redraw => self.show_menu(),
+            MenuAction::Apply(event) => {
+                // Status changes redraw the menu
+                self.handle_event(event);
+            }
+            MenuAction::Close => self.close_menu(),
+        }
+    }
+
+    fn open_menu(&mut self, now: Instant) {
+        info!("Menu opened");
+        self.menu = Some(Menu::new(MENU_TIMEOUT, now));
+        self.show_menu();
+    }
+
+    fn close_menu(&mut self) {
+        info!("Menu closed");
+        self.menu = None;
+
+        if let Err(e) = self.display.show_menu(None) {
+            error!(error = %e, "LCD error");
+        }
+    }
+
+    fn show_menu(&self) {
+        if let Some(menu) = &self.menu {
+            let rows = menu.render(&self.menu_context(), self.display.columns());
+
+            if let Err(e) = self.display.show_menu(Some(rows)) {
+                error!(error = %e, "LCD error");
+            }
+        }
+    }
+
+    fn menu_context(&self) -> MenuContext<'_> {
+        MenuContext {
+            status: &self.status,
+            mqtt_connected: self.mqtt_connected.as_ref().map(|rx| *rx.borrow()),
+            http_address: &self.http_address,
+            alarms: &self.alarms,
+        }
+    }
+
+    /// Refresh the active alarms, lighting the screen for any new ones.
+    fn check_alarms(&mut self) {
+        let alarms = self.current_alarms();
+
+        if alarms.iter().any(|alarm| !self.alarms.contains(alarm)) {
+            warn!(?alarms, "New alarm");
+            if let Err(e) = self.display.alarm() {
+                error!(error = %e, "LCD error");
+            }
+        }
+
+        if alarms != self.alarms {
+            self.alarms = alarms;
+            self.show_menu();
+        }
+    }
+
+    /// No good reading recently, so the temperature can't be trusted.
+    fn sensor_fault(&self) -> bool {
+        !matches!(self.last_reading, Some(at) if at.elapsed() < SENSOR_STALE_AFTER)
+    }
+
+    /// An outdoor temperature has been seen, but not recently.
+    fn outdoor_stale(&self) -> bool {
+        matches!(self.status.outdoor_temperature_updated, Some(at) if at.elapsed() >= OUTDOOR_STALE_AFTER)
+    }
+
+    /// Stop using an outdoor temperature that's stopped updating.
+    fn expire_outdoor_temperature(&mut self) {
+        if self.status.outdoor_temperature.is_some() && self.outdoor_stale() {
+            warn!("Outdoor temperature is stale, ignoring it");
+            self.status.outdoor_temperature = None;
+            self.update_curve_offset();
+        }
+    }
+
+    fn update_curve_offset(&mut self) {
+        let offset = self
+            .status
+            .control
+            .curve
+            .offset(self.status.outdoor_temperature);
+
+        if offset != self.status.curve_offset {
+            info!(
+                curve_offset = offset,
+                outdoor_temperature = ?self.status.outdoor_temperature,
+                "Heating curve adjusted"
+            );
+            self.status.curve_offset = offset;
+        }
+    }
+
+    /// Problems worth showing on the alarms page.
+    fn current_alarms(&self) -> Vec<String> {
+        let mut alarms = Vec::new();
+
+        if self.sensor_fault() {
+            alarms.push("No sensor reading".to_string());
+        } else if self.status.temperature < FROST_ALARM {
+            alarms.push(format!("Room below {}F", FROST_ALARM));
+        }
+
+        if self.outdoor_stale() {
+            alarms.push("No outdoor reading".to_string());
+        }
 
-                toggle_state(&mut relay_pin, &mut status);
+        if let Some(connected) = &self.mqtt_connected {
+            if !*connected.borrow() {
+                alarms.push("MQTT disconnected".to_string());
+            }
+        }
 
-                mqtt_publish(
-                    requests_tx.clone(),
-                    MODE_TOPIC,
-                    if status.running { "heat" } else { "off" },
+        alarms
+    }
+
+    fn apply_editor_action(&mut self, action: EditorAction) {
+        let result = match action {
+            EditorAction::Editing(target) => self.display.edit_target(Some(target)),
+            EditorAction::Commit(target) => {
+                self.handle_event(Event::UpdateTarget(target));
+                self.display.edit_target(None)
+            }
+        };
+
+        if let Err(e) = result {
+            error!(error = %e, "LCD error");
+        }
+    }
+
+    fn schedule_button_timer(&self) {
+        let menu_deadline = self.menu.as_ref().map(Menu::deadline);
+        let deadline = self
+            .editor
+            .next_deadline()
+            .into_iter()
+            .chain(menu_deadline)
+            .min();
+
+        if let Some(deadline) = deadline {
+            let mut events_tx = self.events_tx.clone();
+            tokio::spawn(async move {
+                delay_until(deadline.into()).await;
+                let _ = events_tx.send(Event::ButtonTimer).await;
+            });
+        }
+    }
+
+    fn set_target(&mut self, new_target: f32) {
+        // Also rejects NaN, which would stop the relay ever switching
+        if !TARGET_RANGE.contains(&new_target) {
+            warn!(target_temperature = new_target, "Target out of range");
+            return;
+        }
+        if new_target != self.status.target_temperature {
+            self.stop_autotune("target changed");
+        }
+        self.status.target_temperature = new_target;
+
+        if let Err(e) = write_target_to_file(new_target) {
+            error!(error = %e, "Failed to persist target");
+        }
+
+        info!(target_temperature = new_target, "New target");
+
+        self.drive_relay();
+
+        self.status_changed();
+    }
+
+    /// Switch the relays as the control algorithm says, returning whether any changed.
+    fn drive_relay(&mut self) -> bool {
+        let changed = self.drive_first_stage();
+        self.drive_stages() || changed
+    }
+
+    /// Switch the main relay, returning whether it changed.
+    ///
+    /// With PID control the duty cycle is worked out at the start of each cycle, so a new target
+    /// takes effect from the next one.
+    fn drive_first_stage(&mut self) -> bool {
+        if self.autotune.is_some() {
+            self.anticipator.reset();
+            return self.step_autotune();
+        }
+
+        let was_running = self.status.running;
+
+        if self.status.control.algorithm == Algorithm::Hysteresis || self.status.mode == Mode::Off {
+            self.pid.reset();
+            self.duty_cycle.reset();
+            // Without a trustworthy reading stay off rather than heat blind
+            if !self.sensor_fault() {
+                toggle_state(&mut self.relay_pin, &mut self.status, &self.anticipator);
+            } else if was_running {
+                warn!("No sensor reading, switching heating off");
+                set_relay(&mut self.relay_pin, &mut self.status, false);
+            }
+            self.learn_anticipation();
+
+            return self.status.running != was_running;
+        }
+
+        let now = Instant::now();
+        let config = self.status.control;
+        let mut switch_at = None;
+
+        if self.duty_cycle.due(&config, now) {
+            // Without a trustworthy reading stay off rather than heat blind
+            let output = if self.sensor_fault() {
+                warn!("No sensor reading, skipping heating this cycle");
+                None
+            } else {
+                Some(self.pid.update(
+                    &config.gains,
+                    control_target(&self.status),
+                    effective_temperature(&self.status),
+                    self.duty_cycle.elapsed(now),
+                ))
+            };
+
+            let duty = output.map_or(0.0, |output| output.duty);
+            let on = self.duty_cycle.start(&config, duty, now);
+            info!(duty, on_secs = on.as_secs(), ?output, "Control cycle");
+
+            if let (Some(output), Some(publisher)) = (output, &self.publisher) {
+                publisher.controller_state(&output, on);
+            }
+            switch_at = self.duty_cycle.next_switch(&config, now);
+        }
+
+        let on = self.duty_cycle.relay_on(now);
+        if on != was_running {
+            set_relay(&mut self.relay_pin, &mut self.status, on);
+            switch_at = self.duty_cycle.next_switch(&config, now);
+        }
+
+        // Readings come often enough to switch on time, but not while the sensor is failing
+        if let Some(switch_at) = switch_at {
+            let mut events_tx = self.events_tx.clone();
+            tokio::spawn(async move {
+                delay_until(switch_at.into()).await;
+                let _ = events_tx.send(Event::ControlTimer).await;
+            });
+        }
+
+        on != was_running
+    }
+
+    /// Bring further heating stages in behind the main relay, returning whether any changed.
+    ///
+    /// They stay off during an autotune, which only measures the main relay.
+    fn drive_stages(&mut self) -> bool {
+        let call = self.status.running && self.autotune.is_none();
+        let escalate = !self.sensor_fault();
+        let error = control_target(&self.status) - effective_temperature(&self.status);
+        let outdoor = self.status.outdoor_temperature;
+
+        self.status.aux_locked_out = self.staging.stages.iter().any(|stage| stage.aux)
+            && self.staging.aux_locked_out(outdoor);
+        if !self.stager.update(
+            &self.staging,
+            call,
+            error,
+            escalate,
+            outdoor,
+            Instant::now(),
+        ) {
+            return false;
+        }
+
+        let relays = self.stager.relays();
+        let pins = self.stage_pins.iter_mut().zip(&self.status.stages);
+        for (number, ((pin, &was_on), &on)) in pins.zip(&relays).enumerate() {
+            if on == was_on {
+                continue;
+            }
+            if on {
+                pin.set_high();
+            } else {
+                pin.set_low();
+            }
+            info!(
+                stage = number + 2,
+                error,
+                outdoor_temperature = ?outdoor,
+                "Stage {}",
+                if on { "on" } else { "off" }
+            );
+        }
+        self.status.stages = relays;
+
+        true
+    }
+
+    fn set_target_humidity(&mut self, target: f32) {
+        let humidistat = match &mut self.humidistat {
+            Some(humidistat) => humidistat,
+            None => {
+                warn!(
+                    target_humidity = target,
+                    "No humidity control, ignoring target"
                 );
+                return;
             }
-            Event::Reading {
-                temperature,
-                humidity,
-            } => {
-                status.temperature = temperature;
-                status.humidity = humidity;
+        };
+        if !humidity::valid_target(target) {
+            warn!(target_humidity = target, "Target humidity out of range");
+            return;
+        }
+
+        humidistat.config.target = target;
+        if let Err(e) = humidistat.config.save(HUMIDITY_FILE) {
+            error!(error = %e, "Failed to persist humidity control");
+        }
+        self.status.humidity_control = Some(humidistat.config);
+
+        info!(target_humidity = target, "New target humidity");
+
+        self.drive_humidity();
+
+        self.status_changed();
+    }
+
+    /// Switch the humidity relay around the target, returning whether it changed.
+    ///
+    /// It stays off without a trustworthy reading.
+    fn drive_humidity(&mut self) -> bool {
+        let humidity = if self.sensor_fault() {
+            None
+        } else {
+            Some(self.status.humidity)
+        };
+        let outdoor = self.status.outdoor_temperature;
+        let (humidistat, pin) = match (&mut self.humidistat, &mut self.humidity_pin) {
+            (Some(humidistat), Some(pin)) => (humidistat, pin),
+            _ => return false,
+        };
+
+        self.status.humidity_limit = humidistat.config.limit(outdoor);
+        if !humidistat.update(humidity, outdoor) {
+            return false;
+        }
+
+        let on = humidistat.on();
+        if on {
+            pin.set_high();
+        } else {
+            pin.set_low();
+        }
+        self.status.humidity_relay = on;
+
+        info!(
+            humidity = ?humidity,
+            target_humidity = humidistat.config.effective_target(outdoor),
+            "Humidity relay {}",
+            if on { "on" } else { "off" }
+        );
+
+        true
+    }
+
+    /// Watch how far the temperature carries on past each switch, saving anything learned.
+    fn learn_anticipation(&mut self) {
+        if self.status.mode == Mode::Off || self.sensor_fault() {
+            self.anticipator.reset();
+            return;
+        }
+
+        let target = control_target(&self.status);
+        let learned = self.anticipator.observe(
+            effective_temperature(&self.status),
+            target,
+            self.status.running,
+        );
+        if learned {
+            info!(
+                cut_off = self.anticipator.cut_off(target),
+                cut_in = self.anticipator.cut_in(target),
+                "Anticipation adjusted"
+            );
+            if let Err(e) = self.anticipator.save(ANTICIPATOR_FILE) {
+                error!(error = %e, "Failed to persist anticipation");
+            }
+        }
+    }
 
-                toggle_state(&mut relay_pin, &mut status);
+    /// Time rises towards the target, saving anything learned.
+    fn learn_recovery(&mut self) {
+        if self.status.mode == Mode::Off || self.sensor_fault() {
+            self.recovery.reset();
+            return;
+        }
+
+        let temperature = effective_temperature(&self.status);
+        let learned = self.recovery.observe(
+            temperature,
+            control_target(&self.status),
+            self.status.running,
+            self.status.outdoor_temperature,
+            Instant::now(),
+        );
+        if learned {
+            info!(
+                rate = self
+                    .recovery
+                    .rate(temperature, self.status.outdoor_temperature),
+                "Recovery rate learned"
+            );
+            if let Err(e) = self.recovery.save(RECOVERY_FILE) {
+                error!(error = %e, "Failed to persist recovery rate");
+            }
+        }
+    }
 
-                println!(
-                    "Our Temp: {:.2}, Effective Temp: {:.2} Humidity: {:.2}, Target: {}, Running: {}",
-                    status.temperature, effective_temperature(&status), status.humidity, status.target_temperature, status.running
+    /// Feed the autotune experiment the temperature and switch the relay as it says.
+    fn step_autotune(&mut self) -> bool {
+        let was_running = self.status.running;
+        let temperature = effective_temperature(&self.status);
+        let step = match &mut self.autotune {
+            Some(autotune) => autotune.update(temperature, Instant::now()),
+            None => return false,
+        };
+
+        match step {
+            autotune::Step::Relay(on) => {
+                if on != was_running {
+                    set_relay(&mut self.relay_pin, &mut self.status, on);
+                }
+            }
+            autotune::Step::Done(tuning) => {
+                info!(
+                    ultimate_gain = tuning.ultimate_gain,
+                    ultimate_period_secs = tuning.ultimate_period.as_secs(),
+                    gains = ?tuning.gains,
+                    "Autotune finished"
                 );
+                self.status.control.gains = tuning.gains;
+                if let Err(e) = self.status.control.save(CONTROL_FILE) {
+                    error!(error = %e, "Failed to persist control config");
+                }
+                self.end_autotune();
+            }
+            autotune::Step::Abort(reason) => self.stop_autotune(&reason),
+        }
+
+        self.status.running != was_running
+    }
+
+    fn stop_autotune(&mut self, reason: &str) {
+        if self.autotune.is_some() {
+            warn!(reason, "Autotune aborted");
+            self.end_autotune();
+        }
+    }
+
+    /// Hand the relay back to the configured algorithm.
+    fn end_autotune(&mut self) {
+        self.autotune = None;
+        self.status.autotuning = false;
+        self.pid.reset();
+        self.duty_cycle.reset();
+        self.drive_relay();
+    }
 
-                if let Err(e) = display.update_status(&status) {
-                    eprintln!("LCD Error: {:?}", e);
-                };
+    /// Switch to the scheduled target when a new schedule entry starts, unless on hold.
+    fn apply_schedule(&mut self) {
+        let now = Local::now();
 
-                push_state(requests_tx.clone(), &status).await;
+        if let Some(hold) = self.status.hold {
+            if !hold.expired(now) {
+                self.status.optimal_start = None;
+                return;
             }
-            Event::UpdateDeskTemperature(desk_temperature) => {
-                println!("New Desk Temp: {:.2}", desk_temperature);
 
-                status.desk_temperature = desk_temperature;
-                status.desk_temperature_updated = Instant::now();
+            info!("Hold expired, resuming schedule");
+            self.status.hold = None;
+            self.schedule_entry = None;
+        }
+
+        if let Some((start, entry)) = self.status.schedule.current(now.naive_local()) {
+            // An entry that was started early is already later than the current one
+            if self.schedule_entry.is_none_or(|applied| applied < start) {
+                self.schedule_entry = Some(start);
+                self.status.optimal_start = None;
+                info!(time = %entry.time, "Schedule entry started");
+                self.set_target(entry.target_temperature);
+            }
+        }
+
+        self.plan_optimal_start(now);
+    }
+
+    /// Work out when to start heating for the next scheduled rise, starting it once it's time.
+    fn plan_optimal_start(&mut self, now: DateTime<Local>) {
+        let (start, entry) = match self.status.schedule.next(now.naive_local()) {
+            Some(next) if self.status.mode == Mode::Heat && !self.sensor_fault() => next,
+            _ => {
+                self.status.optimal_start = None;
+                return;
+            }
+        };
+        if self.schedule_entry == Some(start) {
+            // Already started early
+            return;
+        }
+
+        // The heating curve will shift the entry's target the same as the current one
+        let temperature = effective_temperature(&self.status);
+        let entry_target = entry.target_temperature + self.status.curve_offset;
+        let lead = self
+            .recovery
+            .lead(temperature, entry_target, self.status.outdoor_temperature);
+        let heat_from = Local.from_local_datetime(&(start - lead)).earliest();
+        self.status.optimal_start = match heat_from {
+            Some(heat_from)
+                if lead > chrono::Duration::zero()
+                    && entry_target > control_target(&self.status) =>
+            {
+                Some(heat_from)
             }
+            _ => None,
+        };
+
+        if matches!(self.status.optimal_start, Some(heat_from) if now >= heat_from) {
+            info!(
+                time = %entry.time,
+                lead_minutes = lead.num_minutes(),
+                "Starting early for schedule entry"
+            );
+            self.schedule_entry = Some(start);
+            self.set_target(entry.target_temperature);
+        }
+    }
+
+    /// Push the current status out to everything that displays or records it.
+    fn status_changed(&mut self) {
+        self.status.sensor_fault = self.sensor_fault();
+        self.status.mqtt_connected = self.mqtt_connected.as_ref().map(|rx| *rx.borrow());
+        self.check_alarms();
+        self.status.alarms = self.alarms.clone();
+
+        if let Err(e) = self.display.update_status(&self.status) {
+            error!(error = %e, "LCD error");
+        };
+        self.show_menu();
+
+        let _ = self.status_tx.broadcast(self.status.clone());
+        self.history.record(&self.status);
+
+        self.metrics
+            .observe_status(&self.status, effective_temperature(&self.status));
+
+        if let Some(publisher) = &self.publisher {
+            publisher.update(&self.status);
         }
     }
 }
 
-async fn poll_sensor(mut events_tx: Sender<Event>, pin: &mut IoPin) {
+async fn process_events(
+    events_rx: Arc<Mutex<Receiver<Event>>>,
+    controller: Arc<Mutex<Controller>>,
+) -> error::Result<()> {
+    let mut events_rx = events_rx.lock().await;
+
+    while let Some(event) = events_rx.next().await {
+        controller.lock().await.handle_event(event);
+    }
+
+    Ok(())
+}
+
+async fn poll_sensor(
+    mut events_tx: Sender<Event>,
+    pin: &mut IoPin,
+    metrics: Metrics,
+) -> error::Result<()> {
     loop {
         let result = dht::read(pin);
         match result {
@@ -220,19 +1392,111 @@ async fn poll_sensor(mut events_tx: Sender<Event>, pin: &mut IoPin) {
                         humidity,
                     })
                     .await
-                    .unwrap();
+                    .map_err(|_| Error::ChannelClosed("events"))?;
+            }
+            Err(e) => {
+                warn!(error = ?e, "Failed to read sensor");
+                metrics.sensor_error(&e);
+                events_tx
+                    .send(Event::SensorFailed)
+                    .await
+                    .map_err(|_| Error::ChannelClosed("events"))?;
             }
-            Err(e) => eprintln!("Error: {:?}", e),
         }
         delay_for(Duration::from_secs(2)).await;
     }
 }
 
 fn initial_target() -> f32 {
-    read_target_from_file().unwrap_or(DEFAULT_TARGET)
+    read_target_from_file()
+        .ok()
+        .filter(|target| TARGET_RANGE.contains(target))
+        .unwrap_or(DEFAULT_TARGET)
+}
+
+fn initial_mode() -> Mode {
+    std::fs::read_to_string(MODE_FILE)
+        .ok()
+        .and_then(|mode| mode.parse().ok())
+        .unwrap_or(Mode::Heat)
+}
+
+fn write_mode_to_file(mode: Mode) -> Result<(), std::io::Error> {
+    let mut file = File::create(MODE_FILE)?;
+    file.write_all(mode.as_str().as_bytes())
 }
 
-fn read_target_from_file() -> Result<f32, Box<dyn Error>> {
+fn initial_schedule() -> Schedule {
+    match Schedule::load(SCHEDULE_FILE) {
+        Ok(schedule) => schedule,
+        Err(e) => {
+            info!(error = %e, "No schedule loaded");
+            Schedule::default()
+        }
+    }
+}
+
+fn initial_settings() -> Settings {
+    match Settings::load(SETTINGS_FILE) {
+        Ok(settings) => settings,
+        Err(e) => {
+            info!(error = %e, "No settings loaded, using defaults");
+            Settings::default()
+        }
+    }
+}
+
+fn initial_control() -> ControlConfig {
+    match ControlConfig::load(CONTROL_FILE) {
+        Ok(control) => control,
+        Err(e) => {
+            info!(error = %e, "No control config loaded, using hysteresis");
+            ControlConfig::default()
+        }
+    }
+}
+
+fn initial_staging() -> StagingConfig {
+    match StagingConfig::load(STAGING_FILE) {
+        Ok(staging) => staging,
+        Err(e) => {
+            info!(error = %e, "No staging loaded, heating with a single stage");
+            StagingConfig::default()
+        }
+    }
+}
+
+fn initial_humidity() -> Option<HumidityConfig> {
+    match HumidityConfig::load(HUMIDITY_FILE) {
+        Ok(config) => Some(config),
+        Err(e) => {
+            info!(error = %e, "No humidity control loaded");
+            None
+        }
+    }
+}
+
+fn initial_recovery() -> RecoveryModel {
+    match RecoveryModel::load(RECOVERY_FILE) {
+        Ok(recovery) => recovery,
+        Err(e) => {
+            info!(error = %e, "No recovery rates loaded, starting from nothing");
+            RecoveryModel::default()
+        }
+    }
+}
+
+fn initial_anticipator() -> Anticipator {
+    match Anticipator::load(ANTICIPATOR_FILE) {
+        Ok(anticipator) => anticipator,
+        Err(e) => {
+            info!(error = %e, "No anticipation loaded, starting from nothing");
+            Anticipator::default()
+        }
+    }
+}
+
+fn read_target_from_file() -> Result<f32, Box<dyn std::error::Error>> {
     let mut file = File::open(SAVE_FILE)?;
     let mut str_target = String::new();
 
@@ -252,47 +1516,54 @@ fn celcius_to_farenheit(celcius: f32) -> f32 {
     (celcius * 1.8) + 32f32
 }
 
-fn mqtt_publish(mut requests_tx: Sender<Request>, topic: &str, payload: &str) {
-    let message = Publish::new(topic, QoS::AtLeastOnce, payload);
+fn toggle_state(pin: &mut OutputPin, status: &mut Status, anticipator: &Anticipator) {
+    let temperature = effective_temperature(status);
 
-    tokio::spawn(async move {
-        if let Err(e) = timeout(Duration::from_secs(30), requests_tx.send(message.into())).await {
-            println!("publish error {}", e)
-        };
-    });
-}
+    if status.mode == Mode::Off {
+        if status.running {
+            pin.set_low();
+            status.running = false;
+            info!("Relay off, heating is switched off");
+        }
 
-async fn push_state(requests_tx: Sender<Request>, status: &Status) {
-    let temperature = status.temperature.to_string();
-    let humidity = status.humidity.to_string();
-    let target_temperature = status.target_temperature.to_string();
-
-    mqtt_publish(requests_tx.clone(), TEMPERATURE_TOPIC, &temperature);
-    mqtt_publish(requests_tx.clone(), HUMIDITY_TOPIC, &humidity);
-    mqtt_publish(requests_tx.clone(), GET_TARGET_TOPIC, &target_temperature);
-    mqtt_publish(
-        requests_tx,
-        MODE_TOPIC,
-        if status.running { "heat" } else { "off" },
-    );
+        return;
+    }
+
+    let target = control_target(status);
+
+    if status.running && temperature > anticipator.cut_off(target) {
+        set_relay(pin, status, false);
+    } else if !status.running && temperature < anticipator.cut_in(target) {
+        set_relay(pin, status, true);
+    }
 }
 
-fn toggle_state(pin: &mut OutputPin, status: &mut Status) {
-    let temperature = effective_temperature(status);
-    if status.running && temperature > (status.target_temperature + VARIANCE) {
-        pin.set_low();
-        status.running = false;
-    } else if !status.running && temperature < (status.target_temperature - VARIANCE) {
+fn set_relay(pin: &mut OutputPin, status: &mut Status, on: bool) {
+    if on {
         pin.set_high();
-        status.running = true
+    } else {
+        pin.set_low();
     }
+    status.running = on;
+
+    info!(
+        temperature = effective_temperature(status),
+        target_temperature = status.target_temperature,
+        "Relay {}",
+        if on { "on" } else { "off" }
+    );
+}
+
+/// The target the heating aims for, raised by the heating curve when it's cold out.
+fn control_target(status: &Status) -> f32 {
+    status.target_temperature + status.curve_offset
 }
 
 fn effective_temperature(status: &Status) -> f32 {
     let hour = chrono::Local::now().hour();
 
     // Effective temperature is average of local and desk temp during daytime hours
-    if hour >= 7 && hour <= 18 && status.desk_temperature_updated.elapsed() < MAX_TEMPERATURE_LAG {
+    if (7..=18).contains(&hour) && status.desk_temperature_updated.elapsed() < MAX_TEMPERATURE_LAG {
         (status.temperature + status.desk_temperature) / 2.0
     } else {
         status.temperature
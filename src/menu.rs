@@ -0,0 +1,494 @@
+use crate::buttons::{Button, ButtonEvent};
+use crate::schedule::Hold;
+use crate::settings::{Settings, Units};
+use crate::{Event, Mode, Status};
+use chrono::{DateTime, Duration as ChronoDuration, Local};
+use std::time::{Duration, Instant};
+
+const PAGES: [Page; 7] = [
+    Page::Mode,
+    Page::Hold,
+    Page::Units,
+    Page::Backlight,
+    Page::Calibration,
+    Page::Network,
+    Page::Alarms,
+];
+const HOLD_CHOICES: [HoldChoice; 6] = [
+    HoldChoice::Off,
+    HoldChoice::Indefinite,
+    HoldChoice::Hours(1),
+    HoldChoice::Hours(2),
+    HoldChoice::Hours(4),
+    HoldChoice::Hours(8),
+];
+const BACKLIGHT_CHOICES: [Option<u64>; 5] = [Some(10), Some(30), Some(60), Some(300), None];
+const CALIBRATION_STEP: f32 = 0.5;
+const CALIBRATION_LIMIT: f32 = 5.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Page {
+    Mode,
+    Hold,
+    Units,
+    Backlight,
+    Calibration,
+    Network,
+    Alarms,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum HoldChoice {
+    Off,
+    Indefinite,
+    Hours(i64),
+}
+
+/// A value being changed on the current page, indexes are into the matching `*_CHOICES`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Edit {
+    Mode(Mode),
+    Hold(usize),
+    Units(Units),
+    Backlight(usize),
+    Calibration(f32),
+}
+
+/// What the menu needs to know about the rest of the thermostat.
+#[derive(Debug)]
+pub(crate) struct MenuContext<'a> {
+    pub(crate) status: &'a Status,
+    /// `None` when running without MQTT
+    pub(crate) mqtt_connected: Option<bool>,
+    pub(crate) http_address: &'a str,
+    pub(crate) alarms: &'a [String],
+}
+
+/// What the controller should do after a button event in the menu.
+#[derive(Debug)]
+pub(crate) enum MenuAction {
+    Redraw,
+    /// Handle this event as if it came from MQTT or HTTP, then redraw
+    Apply(Event),
+    Close,
+}
+
+/// Settings pages shown on the LCD in place of the status screen.
+///
+/// Up/down move between pages, a long press (or select) starts editing the page's value and a
+/// second one saves it. Pressing both buttons backs out of an edit, or out of the menu.
+#[derive(Debug)]
+pub(crate) struct Menu {
+    page: usize,
+    edit: Option<Edit>,
+    timeout: Duration,
+    close_at: Instant,
+}
+
+impl Menu {
+    pub(crate) fn new(timeout: Duration, now: Instant) -> Self {
+        Self {
+            page: 0,
+            edit: None,
+            timeout,
+            close_at: now + timeout,
+        }
+    }
+
+    pub(crate) fn handle(
+        &mut self,
+        event: ButtonEvent,
+        context: &MenuContext,
+        now: Instant,
+    ) -> MenuAction {
+        self.close_at = now + self.timeout;
+
+        match (self.edit, event) {
+            (Some(edit), ButtonEvent::Short(button)) => {
+                self.edit = Some(edit.step(button));
+                MenuAction::Redraw
+            }
+            (Some(edit), ButtonEvent::Long(_)) | (Some(edit), ButtonEvent::Select) => {
+                self.edit = None;
+                MenuAction::Apply(edit.apply(context.status))
+            }
+            (Some(_), ButtonEvent::Combo) => {
+                self.edit = None;
+                MenuAction::Redraw
+            }
+            (None, ButtonEvent::Short(button)) => {
+                self.page = match button {
+                    Button::Up => (self.page + PAGES.len() - 1) % PAGES.len(),
+                    Button::Down => (self.page + 1) % PAGES.len(),
+                };
+                MenuAction::Redraw
+            }
+            (None, ButtonEvent::Long(_)) | (None, ButtonEvent::Select) => {
+                self.edit = self.page().start_edit(context.status);
+                MenuAction::Redraw
+            }
+            (None, ButtonEvent::Combo) => MenuAction::Close,
+            // Repeats only ever follow the long press that was already handled
+            (_, ButtonEvent::Repeat(_)) => MenuAction::Redraw,
+        }
+    }
+
+    /// When the menu closes itself if no buttons are pressed.
+    pub(crate) fn deadline(&self) -> Instant {
+        self.close_at
+    }
+
+    /// The four rows for the current page, `width` characters wide.
+    ///
+    /// Title and value come first for panels that only fit two rows.
+    pub(crate) fn render(&self, context: &MenuContext, width: usize) -> Vec<String> {
+        let page = self.page();
+        let position = format!("{}/{}", self.page + 1, PAGES.len());
+        let title = format!(
+            "{:<width$.width$}{}",
+            page.title(),
+            position,
+            width = width.saturating_sub(position.len())
+        );
+
+        let (value, detail) = match self.edit {
+            Some(edit) => (format!("> {} <", edit.label()), String::new()),
+            None => page.describe(context),
+        };
+
+        let hint = match (self.edit, page.start_edit(context.status)) {
+            (Some(_), _) => "Hold:save Both:undo",
+            (None, Some(_)) => "Hold:edit Both:exit",
+            (None, None) => "Up/Dn:page Both:exit",
+        };
+
+        vec![title, value, detail, hint.to_string()]
+            .into_iter()
+            .map(|line| format!("{:<width$.width$}", line, width = width))
+            .collect()
+    }
+
+    fn page(&self) -> Page {
+        PAGES[self.page]
+    }
+}
+
+impl Page {
+    fn title(self) -> &'static str {
+        match self {
+            Page::Mode => "Mode",
+            Page::Hold => "Schedule hold",
+            Page::Units => "Units",
+            Page::Backlight => "Backlight timeout",
+            Page::Calibration => "Calibration",
+            Page::Network => "Network",
+            Page::Alarms => "Alarms",
+        }
+    }
+
+    /// The current value as two lines of text.
+    fn describe(self, context: &MenuContext) -> (String, String) {
+        let settings = &context.status.settings;
+
+        match self {
+            Page::Mode => (Edit::Mode(context.status.mode).label(), String::new()),
+            Page::Hold => match context.status.hold {
+                None => (
+                    HoldChoice::Off.label(),
+                    context
+                        .status
+                        .optimal_start
+                        .map(|start| format!("Heat from {}", start.format("%H:%M")))
+                        .unwrap_or_default(),
+                ),
+                Some(Hold::Indefinite) => (HoldChoice::Indefinite.label(), String::new()),
+                Some(Hold::Until(until)) => (
+                    "Held".to_string(),
+                    format!("until {}", until.format("%H:%M")),
+                ),
+            },
+            Page::Units => (Edit::Units(settings.units).label(), String::new()),
+            Page::Backlight => (
+                backlight_label(settings.backlight_timeout_secs),
+                String::new(),
+            ),
+            Page::Calibration => (
+                Edit::Calibration(settings.calibration_offset).label(),
+                String::new(),
+            ),
+            Page::Network => {
+                let mqtt = match context.mqtt_connected {
+                    Some(true) => "connected",
+                    Some(false) => "offline",
+                    None => "disabled",
+                };
+                (
+                    format!("MQTT {}", mqtt),
+                    format!("HTTP {}", context.http_address),
+                )
+            }
+            Page::Alarms => match context.alarms {
+                [] => ("No alarms".to_string(), String::new()),
+                [only] => (only.clone(), String::new()),
+                [first, second] => (first.clone(), second.clone()),
+                [first, rest @ ..] => (first.clone(), format!("+{} more", rest.len())),
+            },
+        }
+    }
+
+    /// Start editing from the current value, `None` for read-only pages.
+    fn start_edit(self, status: &Status) -> Option<Edit> {
+        let settings = &status.settings;
+
+        match self {
+            Page::Mode => Some(Edit::Mode(status.mode)),
+            Page::Hold => Some(Edit::Hold(
+                HOLD_CHOICES
+                    .iter()
+                    .position(|&choice| choice == HoldChoice::current(status.hold, Local::now()))
+                    .unwrap_or(0),
+            )),
+            Page::Units => Some(Edit::Units(settings.units)),
+            Page::Backlight => Some(Edit::Backlight(
+                BACKLIGHT_CHOICES
+                    .iter()
+                    .position(|&choice| choice == settings.backlight_timeout_secs)
+                    .unwrap_or(0),
+            )),
+            Page::Calibration => Some(Edit::Calibration(settings.calibration_offset)),
+            Page::Network | Page::Alarms => None,
+        }
+    }
+}
+
+impl Edit {
+    fn step(self, button: Button) -> Self {
+        let forward = button == Button::Up;
+        let cycle = |index: usize, len: usize| {
+            if forward {
+                (index + 1) % len
+            } else {
+                (index + len - 1) % len
+            }
+        };
+
+        match self {
+            Edit::Mode(Mode::Heat) => Edit::Mode(Mode::Off),
+            Edit::Mode(Mode::Off) => Edit::Mode(Mode::Heat),
+            Edit::Hold(index) => Edit::Hold(cycle(index, HOLD_CHOICES.len())),
+            Edit::Units(Units::Fahrenheit) => Edit::Units(Units::Celsius),
+            Edit::Units(Units::Celsius) => Edit::Units(Units::Fahrenheit),
+            Edit::Backlight(index) => Edit::Backlight(cycle(index, BACKLIGHT_CHOICES.len())),
+            Edit::Calibration(offset) => {
+                let step = if forward {
+                    CALIBRATION_STEP
+                } else {
+                    -CALIBRATION_STEP
+                };
+                Edit::Calibration((offset + step).clamp(-CALIBRATION_LIMIT, CALIBRATION_LIMIT))
+            }
+        }
+    }
+
+    fn apply(self, status: &Status) -> Event {
+        let settings = status.settings;
+
+        match self {
+            Edit::Mode(mode) => Event::SetMode(mode),
+            Edit::Hold(index) => Event::SetHold(match HOLD_CHOICES[index] {
+                HoldChoice::Off => None,
+                HoldChoice::Indefinite => Some(Hold::Indefinite),
+                HoldChoice::Hours(hours) => {
+                    Some(Hold::Until(Local::now() + ChronoDuration::hours(hours)))
+                }
+            }),
+            Edit::Units(units) => Event::UpdateSettings(Settings { units, ..settings }),
+            Edit::Backlight(index) => Event::UpdateSettings(Settings {
+                backlight_timeout_secs: BACKLIGHT_CHOICES[index],
+                ..settings
+            }),
+            Edit::Calibration(calibration_offset) => Event::UpdateSettings(Settings {
+                calibration_offset,
+                ..settings
+            }),
+        }
+    }
+
+    fn label(self) -> String {
+        match self {
+            Edit::Mode(mode) => match mode {
+                Mode::Heat => "Heat".to_string(),
+                Mode::Off => "Off".to_string(),
+            },
+            Edit::Hold(index) => HOLD_CHOICES[index].label(),
+            Edit::Units(Units::Fahrenheit) => "Fahrenheit".to_string(),
+            Edit::Units(Units::Celsius) => "Celsius".to_string(),
+            Edit::Backlight(index) => backlight_label(BACKLIGHT_CHOICES[index]),
+            Edit::Calibration(offset) => format!("{:+.1}F", offset),
+        }
+    }
+}
+
+impl HoldChoice {
+    /// The choice closest to this hold, a timed one by its time left, so saving without changing
+    /// anything keeps it held.
+    fn current(hold: Option<Hold>, now: DateTime<Local>) -> Self {
+        match hold {
+            Some(hold) if hold.expired(now) => HoldChoice::Off,
+            None => HoldChoice::Off,
+            Some(Hold::Indefinite) => HoldChoice::Indefinite,
+            Some(Hold::Until(until)) => {
+                let left = (until - now).num_minutes();
+                HOLD_CHOICES
+                    .iter()
+                    .copied()
+                    .filter_map(|choice| match choice {
+                        HoldChoice::Hours(hours) => Some((choice, (hours * 60 - left).abs())),
+                        _ => None,
+                    })
+                    .min_by_key(|&(_, distance)| distance)
+                    .map_or(HoldChoice::Indefinite, |(choice, _)| choice)
+            }
+        }
+    }
+
+    fn label(self) -> String {
+        match self {
+            HoldChoice::Off => "Off (schedule)".to_string(),
+            HoldChoice::Indefinite => "Indefinite".to_string(),
+            HoldChoice::Hours(1) => "1 hour".to_string(),
+            HoldChoice::Hours(hours) => format!("{} hours", hours),
+        }
+    }
+}
+
+fn backlight_label(secs: Option<u64>) -> String {
+    match secs {
+        None => "Always on".to_string(),
+        Some(secs) if secs < 60 => format!("{} seconds", secs),
+        Some(secs) => format!("{} min", secs / 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(30);
+
+    fn context<'a>(status: &'a Status, alarms: &'a [String]) -> MenuContext<'a> {
+        MenuContext {
+            status,
+            mqtt_connected: Some(true),
+            http_address: "0.0.0.0:9100",
+            alarms,
+        }
+    }
+
+    #[test]
+    fn edits_and_applies_mode() {
+        let status = Status::new(70.0, false);
+        let context = context(&status, &[]);
+        let now = Instant::now();
+        let mut menu = Menu::new(TIMEOUT, now);
+
+        menu.handle(ButtonEvent::Long(Button::Up), &context, now);
+        assert_eq!(menu.render(&context, 20)[1].trim_end(), "> Heat <");
+
+        menu.handle(ButtonEvent::Short(Button::Up), &context, now);
+        match menu.handle(ButtonEvent::Long(Button::Up), &context, now) {
+            MenuAction::Apply(Event::SetMode(Mode::Off)) => {}
+            action => panic!("unexpected {:?}", action),
+        }
+    }
+
+    #[test]
+    fn combo_cancels_edit_then_closes() {
+        let status = Status::new(70.0, false);
+        let context = context(&status, &[]);
+        let now = Instant::now();
+        let mut menu = Menu::new(TIMEOUT, now);
+
+        menu.handle(ButtonEvent::Short(Button::Down), &context, now);
+        menu.handle(ButtonEvent::Long(Button::Down), &context, now);
+        menu.handle(ButtonEvent::Short(Button::Up), &context, now);
+        assert!(matches!(
+            menu.handle(ButtonEvent::Combo, &context, now),
+            MenuAction::Redraw
+        ));
+        assert_eq!(menu.render(&context, 20)[1].trim_end(), "Off (schedule)");
+        assert!(matches!(
+            menu.handle(ButtonEvent::Combo, &context, now),
+            MenuAction::Close
+        ));
+    }
+
+    #[test]
+    fn renders_read_only_pages_to_fit() {
+        let status = Status::new(70.0, false);
+        let alarms = vec![
+            "No sensor reading".to_string(),
+            "MQTT disconnected".to_string(),
+        ];
+        let context = context(&status, &alarms);
+        let now = Instant::now();
+        let mut menu = Menu::new(TIMEOUT, now);
+
+        // Wraps backwards from the first page to the last
+        menu.handle(ButtonEvent::Short(Button::Up), &context, now);
+        let lines = menu.render(&context, 20);
+
+        assert_eq!(
+            lines,
+            vec![
+                "Alarms           7/7",
+                "No sensor reading   ",
+                "MQTT disconnected   ",
+                "Up/Dn:page Both:exit",
+            ]
+        );
+        // Read-only, so a long press doesn't start editing
+        menu.handle(ButtonEvent::Long(Button::Up), &context, now);
+        assert_eq!(menu.render(&context, 20)[3], "Up/Dn:page Both:exit");
+        // The position stays visible on narrower panels
+        assert_eq!(menu.render(&context, 16)[0], "Alarms       7/7");
+    }
+
+    #[test]
+    fn hold_edit_starts_from_the_current_hold() {
+        let now = Local::now();
+        let choice = |hold| HoldChoice::current(hold, now);
+
+        assert_eq!(choice(None), HoldChoice::Off);
+        assert_eq!(choice(Some(Hold::Indefinite)), HoldChoice::Indefinite);
+        assert_eq!(
+            choice(Some(Hold::Until(now + ChronoDuration::minutes(100)))),
+            HoldChoice::Hours(2)
+        );
+        assert_eq!(
+            choice(Some(Hold::Until(now + ChronoDuration::minutes(190)))),
+            HoldChoice::Hours(4)
+        );
+        assert_eq!(
+            choice(Some(Hold::Until(now + ChronoDuration::hours(30)))),
+            HoldChoice::Hours(8)
+        );
+        assert_eq!(
+            choice(Some(Hold::Until(now - ChronoDuration::minutes(1)))),
+            HoldChoice::Off
+        );
+
+        let mut status = Status::new(70.0, false);
+        status.hold = Some(Hold::Indefinite);
+        assert_eq!(Page::Hold.start_edit(&status), Some(Edit::Hold(1)));
+    }
+
+    #[test]
+    fn calibration_is_clamped() {
+        let mut edit = Edit::Calibration(4.5);
+
+        edit = edit.step(Button::Up).step(Button::Up);
+
+        assert_eq!(edit, Edit::Calibration(CALIBRATION_LIMIT));
+    }
+}
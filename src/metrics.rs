@@ -0,0 +1,230 @@
+use crate::dht::ReadingError;
+use crate::Status;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Shared counters and gauges, rendered in the Prometheus text format for `/metrics`.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Metrics {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    status: Option<StatusGauges>,
+    relay_on: Option<bool>,
+    heating_since: Option<Instant>,
+    heating_total: Duration,
+    relay_cycles: u64,
+    dht_timeouts: u64,
+    dht_checksum_errors: u64,
+    mqtt_reconnects: u64,
+    publish_failures: u64,
+}
+
+#[derive(Debug)]
+struct StatusGauges {
+    temperature: f32,
+    humidity: f32,
+    effective_temperature: f32,
+    target_temperature: f32,
+    desk_temperature_updated: Instant,
+}
+
+impl Metrics {
+    pub(crate) fn observe_status(&self, status: &Status, effective_temperature: f32) {
+        let mut inner = self.lock();
+
+        inner.status = Some(StatusGauges {
+            temperature: status.temperature,
+            humidity: status.humidity,
+            effective_temperature,
+            target_temperature: status.target_temperature,
+            desk_temperature_updated: status.desk_temperature_updated,
+        });
+
+        let was_on = inner.relay_on.replace(status.running);
+        match (was_on, status.running) {
+            (Some(false), true) => {
+                inner.relay_cycles += 1;
+                inner.heating_since = Some(Instant::now());
+            }
+            (None, true) => inner.heating_since = Some(Instant::now()),
+            (_, false) => {
+                if let Some(since) = inner.heating_since.take() {
+                    inner.heating_total += since.elapsed();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub(crate) fn sensor_error(&self, error: &ReadingError) {
+        let mut inner = self.lock();
+
+        match error {
+            ReadingError::Timeout => inner.dht_timeouts += 1,
+            ReadingError::Checksum => inner.dht_checksum_errors += 1,
+        }
+    }
+
+    pub(crate) fn mqtt_reconnect(&self) {
+        self.lock().mqtt_reconnects += 1;
+    }
+
+    pub(crate) fn publish_failure(&self) {
+        self.lock().publish_failures += 1;
+    }
+
+    pub(crate) fn render(&self) -> String {
+        let inner = self.lock();
+        let mut out = String::new();
+
+        if let Some(status) = &inner.status {
+            gauge(
+                &mut out,
+                "thermostat_temperature_fahrenheit",
+                "Temperature measured by the local sensor",
+                status.temperature,
+            );
+            gauge(
+                &mut out,
+                "thermostat_humidity_percent",
+                "Relative humidity measured by the local sensor",
+                status.humidity,
+            );
+            gauge(
+                &mut out,
+                "thermostat_effective_temperature_fahrenheit",
+                "Temperature used for control, including the desk sensor when fresh",
+                status.effective_temperature,
+            );
+            gauge(
+                &mut out,
+                "thermostat_target_temperature_fahrenheit",
+                "Target temperature",
+                status.target_temperature,
+            );
+            gauge(
+                &mut out,
+                "thermostat_desk_temperature_age_seconds",
+                "Time since the last desk temperature update",
+                status.desk_temperature_updated.elapsed().as_secs_f32(),
+            );
+        }
+
+        if let Some(relay_on) = inner.relay_on {
+            gauge(
+                &mut out,
+                "thermostat_relay_on",
+                "Whether the heating relay is on",
+                if relay_on { 1 } else { 0 },
+            );
+        }
+
+        let heating = inner.heating_total
+            + inner
+                .heating_since
+                .map(|since| since.elapsed())
+                .unwrap_or_default();
+
+        header(
+            &mut out,
+            "thermostat_sensor_errors_total",
+            "Failed DHT sensor reads",
+            "counter",
+        );
+        let _ = writeln!(
+            out,
+            "thermostat_sensor_errors_total{{kind=\"timeout\"}} {}",
+            inner.dht_timeouts
+        );
+        let _ = writeln!(
+            out,
+            "thermostat_sensor_errors_total{{kind=\"checksum\"}} {}",
+            inner.dht_checksum_errors
+        );
+        counter(
+            &mut out,
+            "thermostat_relay_cycles_total",
+            "Times the heating relay has switched on",
+            inner.relay_cycles,
+        );
+        counter(
+            &mut out,
+            "thermostat_heating_seconds_total",
+            "Cumulative time the heating relay has been on",
+            heating.as_secs_f64(),
+        );
+        counter(
+            &mut out,
+            "thermostat_mqtt_reconnects_total",
+            "MQTT reconnection attempts",
+            inner.mqtt_reconnects,
+        );
+        counter(
+            &mut out,
+            "thermostat_mqtt_publish_failures_total",
+            "MQTT publishes that couldn't be sent or buffered",
+            inner.publish_failures,
+        );
+
+        out
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        // Metrics are only ever simple counter updates, a panic while holding the lock can't
+        // leave them in a state worth refusing to read
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: impl std::fmt::Display) {
+    header(out, name, help, "gauge");
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: impl std::fmt::Display) {
+    header(out, name, help, "counter");
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_gauges_and_counters() {
+        let metrics = Metrics::default();
+        let mut status = Status::new(70.0, false);
+        status.temperature = 68.5;
+        status.humidity = 40.0;
+
+        metrics.observe_status(&status, 68.5);
+        status.running = true;
+        metrics.observe_status(&status, 68.5);
+        metrics.sensor_error(&ReadingError::Timeout);
+        metrics.sensor_error(&ReadingError::Timeout);
+        metrics.sensor_error(&ReadingError::Checksum);
+        metrics.mqtt_reconnect();
+
+        let output = metrics.render();
+
+        assert!(output.contains("thermostat_temperature_fahrenheit 68.5\n"));
+        assert!(output.contains("thermostat_target_temperature_fahrenheit 70\n"));
+        assert!(output.contains("thermostat_relay_on 1\n"));
+        assert!(output.contains("thermostat_relay_cycles_total 1\n"));
+        assert!(output.contains("thermostat_sensor_errors_total{kind=\"timeout\"} 2\n"));
+        assert!(output.contains("thermostat_sensor_errors_total{kind=\"checksum\"} 1\n"));
+        assert!(output.contains("thermostat_mqtt_reconnects_total 1\n"));
+        assert!(output.contains("# TYPE thermostat_heating_seconds_total counter\n"));
+    }
+}
@@ -0,0 +1,209 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, prelude::*, BufReader};
+use std::path::PathBuf;
+
+/// A single telemetry message captured while the broker was unreachable.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Record {
+    pub(crate) timestamp: DateTime<Utc>,
+    pub(crate) topic: String,
+    pub(crate) payload: String,
+}
+
+impl Record {
+    pub(crate) fn new(topic: &str, payload: &str) -> Self {
+        Self {
+            timestamp: Utc::now(),
+            topic: topic.to_string(),
+            payload: payload.to_string(),
+        }
+    }
+
+    /// Payload sent to the replay topic, carrying the original timestamp and topic.
+    pub(crate) fn replay_payload(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+
+    fn to_line(&self) -> String {
+        format!(
+            "{}\t{}\t{}\n",
+            self.timestamp.to_rfc3339(),
+            self.topic,
+            self.payload
+        )
+    }
+
+    fn from_line(line: &str) -> Option<Self> {
+        let mut parts = line.splitn(3, '\t');
+        let timestamp = DateTime::parse_from_rfc3339(parts.next()?).ok()?;
+        let topic = parts.next()?;
+        let payload = parts.next()?;
+
+        Some(Self {
+            timestamp: timestamp.with_timezone(&Utc),
+            topic: topic.to_string(),
+            payload: payload.to_string(),
+        })
+    }
+}
+
+/// Bounded, append-only file of telemetry waiting to be sent to the broker.
+///
+/// Records survive restarts. Once more than `capacity` records are stored the oldest ones are
+/// dropped.
+#[derive(Debug)]
+pub(crate) struct OfflineQueue {
+    path: PathBuf,
+    capacity: usize,
+    len: usize,
+}
+
+impl OfflineQueue {
+    pub(crate) fn open(path: impl Into<PathBuf>, capacity: usize) -> Self {
+        let mut queue = Self {
+            path: path.into(),
+            capacity,
+            len: 0,
+        };
+        queue.len = queue.records().map(|records| records.len()).unwrap_or(0);
+
+        queue
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub(crate) fn push(&mut self, record: &Record) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(record.to_line().as_bytes())?;
+        self.len += 1;
+
+        // Allow some slack so we aren't rewriting the whole file on every push once full
+        if self.len > self.capacity + self.capacity / 10 {
+            self.compact()?;
+        }
+
+        Ok(())
+    }
+
+    /// All stored records, oldest first.
+    pub(crate) fn records(&self) -> io::Result<Vec<Record>> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut records = Vec::new();
+        for line in BufReader::new(file).lines() {
+            if let Some(record) = Record::from_line(&line?) {
+                records.push(record);
+            }
+        }
+
+        Ok(records)
+    }
+
+    pub(crate) fn clear(&mut self) -> io::Result<()> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        self.len = 0;
+
+        Ok(())
+    }
+
+    /// Drop the oldest `count` records, e.g. once they've been sent.
+    pub(crate) fn remove_first(&mut self, count: usize) -> io::Result<()> {
+        let records = self.records()?;
+        self.rewrite(&records[count.min(records.len())..])
+    }
+
+    fn compact(&mut self) -> io::Result<()> {
+        let records = self.records()?;
+        self.rewrite(&records[records.len().saturating_sub(self.capacity)..])
+    }
+
+    fn rewrite(&mut self, keep: &[Record]) -> io::Result<()> {
+        let tmp_path = self.path.with_extension("tmp");
+        let mut file = File::create(&tmp_path)?;
+        for record in keep {
+            file.write_all(record.to_line().as_bytes())?;
+        }
+        fs::rename(tmp_path, &self.path)?;
+        self.len = keep.len();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "thermostat-offline-{}-{}.txt",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn records_round_trip_in_order() {
+        let path = temp_path("round-trip");
+        let mut queue = OfflineQueue::open(&path, 10);
+        let first = Record::new("a/topic", "70.1");
+        let second = Record::new("b/topic", "heat");
+
+        queue.push(&first).unwrap();
+        queue.push(&second).unwrap();
+
+        let reopened = OfflineQueue::open(&path, 10);
+        assert!(!reopened.is_empty());
+        assert_eq!(reopened.records().unwrap(), vec![first, second.clone()]);
+
+        queue.remove_first(1).unwrap();
+        assert_eq!(queue.records().unwrap(), vec![second]);
+
+        queue.clear().unwrap();
+        assert!(queue.is_empty());
+        assert_eq!(queue.records().unwrap(), vec![]);
+    }
+
+    #[test]
+    fn replay_payload_is_escaped() {
+        let record = Record::new("a/topic", r#"say "hi" \ bye"#);
+        let payload: serde_json::Value =
+            serde_json::from_str(&record.replay_payload().unwrap()).unwrap();
+
+        assert_eq!(payload["topic"], "a/topic");
+        assert_eq!(payload["payload"], r#"say "hi" \ bye"#);
+    }
+
+    #[test]
+    fn drops_oldest_records_when_full() {
+        let path = temp_path("capacity");
+        let mut queue = OfflineQueue::open(&path, 10);
+
+        for i in 0..25 {
+            queue.push(&Record::new("topic", &i.to_string())).unwrap();
+        }
+
+        let records = queue.records().unwrap();
+        assert!(records.len() <= 11);
+        assert_eq!(records.last().unwrap().payload, "24");
+        assert!(records.first().unwrap().payload.parse::<usize>().unwrap() >= 14);
+
+        queue.clear().unwrap();
+    }
+}
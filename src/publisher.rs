@@ -0,0 +1,468 @@
+use crate::control::PidOutput;
+use crate::error::{self, Error};
+use crate::humidity::Equipment;
+use crate::metrics::Metrics;
+use crate::offline::{OfflineQueue, Record};
+use crate::supervisor::supervise;
+use crate::{
+    Mode, Status, ACTION_TOPIC, ALARM_TOPIC, BACKLIGHT_STATE_TOPIC, CONTROLLER_STATE_TOPIC,
+    DISPLAY_STATE_TOPIC, GET_TARGET_HUMIDITY_TOPIC, GET_TARGET_TOPIC, HUMIDITY_ACTION_TOPIC,
+    HUMIDITY_DEADBAND, HUMIDITY_TOPIC, MODE_TOPIC, OFFLINE_QUEUE_CAPACITY, OFFLINE_QUEUE_FILE,
+    OPTIMAL_START_TOPIC, PUBLISH_HEARTBEAT, REPLAY_TOPIC, STAGES_TOPIC, TEMPERATURE_DEADBAND,
+    TEMPERATURE_TOPIC,
+};
+use chrono::{DateTime, Local};
+use rumq_client::{Publish, QoS, Request};
+use serde::Serialize;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::Sender;
+use tokio::sync::watch;
+use tokio::time::delay_for;
+use tracing::{debug, error, info, info_span, warn, Instrument};
+
+/// Handle for pushing status snapshots to the MQTT publisher task.
+///
+/// Only the most recent snapshot is kept, so if the broker is slow updates are coalesced rather
+/// than piling up as pending publishes. While disconnected, telemetry is written to an on-disk
+/// queue instead and replayed with its original timestamps once the broker is back.
+#[derive(Debug)]
+pub(crate) struct Publisher {
+    state: watch::Sender<Option<Status>>,
+    requests_tx: Sender<Request>,
+    connected_rx: watch::Receiver<bool>,
+}
+
+/// Payload for the controller state topic.
+#[derive(Serialize)]
+struct ControllerState<'a> {
+    #[serde(flatten)]
+    output: &'a PidOutput,
+    on_secs: u64,
+}
+
+impl Publisher {
+    pub(crate) fn new(
+        requests_tx: Sender<Request>,
+        connected_rx: watch::Receiver<bool>,
+        metrics: Metrics,
+    ) -> Self {
+        let (state_tx, state_rx) = watch::channel(None);
+
+        {
+            let connected_rx = connected_rx.clone();
+            let requests_tx = requests_tx.clone();
+            // Everything worth keeping across a restart is on disk or in the channels
+            supervise("mqtt publisher", move || {
+                publish_loop(
+                    state_rx.clone(),
+                    connected_rx.clone(),
+                    requests_tx.clone(),
+                    metrics.clone(),
+                )
+                .instrument(info_span!("mqtt"))
+            });
+        }
+
+        Self {
+            state: state_tx,
+            requests_tx,
+            connected_rx,
+        }
+    }
+
+    pub(crate) fn update(&self, status: &Status) {
+        if self.state.broadcast(Some(status.clone())).is_err() {
+            warn!("MQTT publisher has stopped, dropping state update");
+        }
+    }
+
+    /// Publish the terms of a PID cycle, along with how long the relay is on for.
+    ///
+    /// Only useful live while tuning, so these are dropped rather than queued when offline or
+    /// when the broker is backed up.
+    pub(crate) fn controller_state(&self, output: &PidOutput, on: Duration) {
+        if !*self.connected_rx.borrow() {
+            return;
+        }
+
+        let state = ControllerState {
+            output,
+            on_secs: on.as_secs(),
+        };
+        let payload = match serde_json::to_string(&state) {
+            Ok(payload) => payload,
+            Err(e) => {
+                error!(error = %e, "Failed to serialize controller state");
+                return;
+            }
+        };
+
+        let message = Publish::new(CONTROLLER_STATE_TOPIC, QoS::AtMostOnce, payload);
+        if self.requests_tx.clone().try_send(message.into()).is_err() {
+            debug!("MQTT requests backed up, dropping controller state");
+        }
+    }
+}
+
+async fn publish_loop(
+    mut state_rx: watch::Receiver<Option<Status>>,
+    mut connected_rx: watch::Receiver<bool>,
+    mut requests_tx: Sender<Request>,
+    metrics: Metrics,
+) -> error::Result<()> {
+    let mut queue = OfflineQueue::open(OFFLINE_QUEUE_FILE, OFFLINE_QUEUE_CAPACITY);
+    let mut connected = false;
+    let mut client_stopped = false;
+    let mut fields = [
+        TrackedField::new(TEMPERATURE_TOPIC, TEMPERATURE_DEADBAND),
+        TrackedField::new(HUMIDITY_TOPIC, HUMIDITY_DEADBAND),
+        TrackedField::new(GET_TARGET_TOPIC, 0.0),
+        TrackedField::new(MODE_TOPIC, 0.0),
+        TrackedField::new(ACTION_TOPIC, 0.0),
+        TrackedField::new(BACKLIGHT_STATE_TOPIC, 0.0),
+        TrackedField::new(DISPLAY_STATE_TOPIC, 0.0),
+        TrackedField::new(ALARM_TOPIC, 0.0),
+        TrackedField::new(OPTIMAL_START_TOPIC, 0.0),
+        TrackedField::new(STAGES_TOPIC, 0.0),
+        TrackedField::new(GET_TARGET_HUMIDITY_TOPIC, 0.0),
+        TrackedField::new(HUMIDITY_ACTION_TOPIC, 0.0),
+    ];
+    let mut latest: Option<Status> = None;
+
+    loop {
+        // Wake up either for a new snapshot or when the next heartbeat is due
+        let now = Instant::now();
+        let wait = fields
+            .iter()
+            .filter_map(|field| field.heartbeat_due(now))
+            .min()
+            .unwrap_or(PUBLISH_HEARTBEAT);
+
+        tokio::select! {
+            status = state_rx.recv() => match status {
+                Some(status) => latest = status,
+                None => return Ok(()),
+            },
+            state = connected_rx.recv(), if !client_stopped => match state {
+                Some(state) => {
+                    // Anything published while offline only went to the queue, so subscribers
+                    // need the current state again
+                    if state && !connected {
+                        fields.iter_mut().for_each(TrackedField::forget);
+                    }
+                    connected = state;
+                }
+                None => {
+                    client_stopped = true;
+                    connected = false;
+                }
+            },
+            _ = delay_for(wait) => {}
+        }
+
+        if connected && !queue.is_empty() {
+            if let Err(e) = replay(&mut queue, &mut requests_tx).await {
+                error!(error = %e, "Failed to replay offline telemetry");
+                metrics.publish_failure();
+            }
+        }
+
+        let status = match &latest {
+            Some(status) => status,
+            None => continue,
+        };
+
+        // Numbers are compared against the deadband, anything else republishes when the text changes
+        let values = [
+            numeric(status.temperature),
+            numeric(status.humidity),
+            numeric(status.target_temperature),
+            text(status.mode.as_str()),
+            text(action_payload(status)),
+            text(if status.backlight { "ON" } else { "OFF" }),
+            text(if status.display_healthy {
+                "ONLINE"
+            } else {
+                "OFFLINE"
+            }),
+            text(alarm_payload(&status.alarms)),
+            text(optimal_start_payload(status.optimal_start)),
+            stages_payload(status),
+            status
+                .humidity_control
+                .map_or(text(""), |control| numeric(control.target)),
+            text(humidity_action_payload(status)),
+        ];
+
+        let humidity_control = status.humidity_control.is_some();
+        for (field, (value, payload)) in fields.iter_mut().zip(values.iter()) {
+            let value = *value;
+            let now = Instant::now();
+            if !humidity_control && HUMIDITY_CONTROL_TOPICS.contains(&field.topic) {
+                continue;
+            }
+            if !field.should_publish(value, payload, now) {
+                continue;
+            }
+
+            debug!(topic = field.topic, %payload, connected, "Publishing");
+
+            if connected {
+                let message = Publish::new(field.topic, QoS::AtLeastOnce, payload.as_str());
+
+                // Awaiting here is deliberate, while the broker is slow this blocks and newer
+                // snapshots replace older ones in the watch channel instead of queueing up.
+                if requests_tx.send(message.into()).await.is_err() {
+                    metrics.publish_failure();
+                    return Err(Error::ChannelClosed("mqtt requests"));
+                }
+            } else if let Err(e) = queue.push(&Record::new(field.topic, payload)) {
+                error!(error = %e, "Failed to buffer offline telemetry");
+                metrics.publish_failure();
+            }
+
+            field.published(value, payload, now);
+        }
+    }
+}
+
+/// Send everything buffered while offline to the replay topic, oldest first.
+///
+/// If sending fails part way through only the unsent records are kept, so they aren't sent twice.
+async fn replay(
+    queue: &mut OfflineQueue,
+    requests_tx: &mut Sender<Request>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let records = queue.records()?;
+    info!(records = records.len(), "Replaying offline telemetry");
+
+    for (sent, record) in records.iter().enumerate() {
+        let result: Result<(), Box<dyn std::error::Error>> = match record.replay_payload() {
+            Ok(payload) => {
+                let message = Publish::new(REPLAY_TOPIC, QoS::AtLeastOnce, payload);
+                requests_tx.send(message.into()).await.map_err(Into::into)
+            }
+            Err(e) => Err(e.into()),
+        };
+
+        if let Err(e) = result {
+            queue.remove_first(sent)?;
+            return Err(e);
+        }
+    }
+
+    queue.clear()?;
+
+    Ok(())
+}
+
+fn numeric(value: f32) -> (Option<f32>, String) {
+    (Some(value), value.to_string())
+}
+
+fn text(payload: impl Into<String>) -> (Option<f32>, String) {
+    (None, payload.into())
+}
+
+fn alarm_payload(alarms: &[String]) -> String {
+    if alarms.is_empty() {
+        "none".to_string()
+    } else {
+        alarms.join("; ")
+    }
+}
+
+fn optimal_start_payload(start: Option<DateTime<Local>>) -> String {
+    start.map_or_else(|| "none".to_string(), |start| start.to_rfc3339())
+}
+
+/// Main relay plus any further stages that are on.
+fn stages_payload(status: &Status) -> (Option<f32>, String) {
+    let stages = if status.running {
+        1 + status.stages.iter().filter(|on| **on).count()
+    } else {
+        status.stages.iter().filter(|on| **on).count()
+    };
+
+    numeric(stages as f32)
+}
+
+fn humidity_action_payload(status: &Status) -> &'static str {
+    match status.humidity_control {
+        Some(control) if status.humidity_relay => match control.equipment {
+            Equipment::Humidifier => "humidifying",
+            Equipment::Dehumidifier => "drying",
+        },
+        _ => "idle",
+    }
+}
+
+fn action_payload(status: &Status) -> &'static str {
+    match status.mode {
+        Mode::Off => "off",
+        _ if status.running => "heating",
+        _ => "idle",
+    }
+}
+
+/// Only published with humidity control.
+const HUMIDITY_CONTROL_TOPICS: [&str; 2] = [GET_TARGET_HUMIDITY_TOPIC, HUMIDITY_ACTION_TOPIC];
+
+/// Last published value of a single topic.
+#[derive(Debug)]
+struct TrackedField {
+    topic: &'static str,
+    /// Only applies to numeric values
+    deadband: f32,
+    last: Option<Published>,
+}
+
+#[derive(Debug)]
+struct Published {
+    /// `None` for text, which is compared by its payload
+    value: Option<f32>,
+    payload: String,
+    at: Instant,
+}
+
+impl TrackedField {
+    fn new(topic: &'static str, deadband: f32) -> Self {
+        Self {
+            topic,
+            deadband,
+            last: None,
+        }
+    }
+
+    fn should_publish(&self, value: Option<f32>, payload: &str, now: Instant) -> bool {
+        let last = match &self.last {
+            Some(last) => last,
+            None => return true,
+        };
+
+        now.duration_since(last.at) >= PUBLISH_HEARTBEAT
+            || match (last.value, value) {
+                (Some(last), Some(value)) => exceeds_deadband(last, value, self.deadband),
+                _ => last.payload != payload,
+            }
+    }
+
+    fn published(&mut self, value: Option<f32>, payload: &str, now: Instant) {
+        self.last = Some(Published {
+            value,
+            payload: payload.to_string(),
+            at: now,
+        });
+    }
+
+    /// Republish on the next value, whatever it is.
+    fn forget(&mut self) {
+        self.last = None;
+    }
+
+    /// Time remaining until this field must be republished, if it has been published before.
+    fn heartbeat_due(&self, now: Instant) -> Option<Duration> {
+        self.last
+            .as_ref()
+            .map(|last| (last.at + PUBLISH_HEARTBEAT).saturating_duration_since(now))
+    }
+}
+
+fn exceeds_deadband(last: f32, value: f32, deadband: f32) -> bool {
+    // Small tolerance so a change of exactly one deadband step isn't lost to float rounding
+    last != value && (value - last).abs() + 1e-4 >= deadband
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn should_publish(
+        field: &TrackedField,
+        (value, payload): (Option<f32>, String),
+        now: Instant,
+    ) -> bool {
+        field.should_publish(value, &payload, now)
+    }
+
+    fn published(field: &mut TrackedField, (value, payload): (Option<f32>, String), now: Instant) {
+        field.published(value, &payload, now);
+    }
+
+    #[test]
+    fn publishes_first_value() {
+        let field = TrackedField::new(TEMPERATURE_TOPIC, 0.1);
+
+        assert!(should_publish(&field, numeric(70.0), Instant::now()));
+    }
+
+    #[test]
+    fn skips_changes_within_deadband() {
+        let now = Instant::now();
+        let mut field = TrackedField::new(TEMPERATURE_TOPIC, 0.1);
+        published(&mut field, numeric(70.0), now);
+
+        assert!(!should_publish(&field, numeric(70.0), now));
+        assert!(!should_publish(&field, numeric(70.05), now));
+        assert!(should_publish(&field, numeric(70.1), now));
+        assert!(should_publish(&field, numeric(69.8), now));
+    }
+
+    #[test]
+    fn text_publishes_any_change() {
+        let now = Instant::now();
+        let mut field = TrackedField::new(MODE_TOPIC, 0.0);
+        published(&mut field, text("off"), now);
+
+        assert!(!should_publish(&field, text("off"), now));
+        assert!(should_publish(&field, text("heat"), now));
+
+        field.forget();
+        assert!(should_publish(&field, text("off"), now));
+
+        // Every alarm and start time is told apart, not just a summary of them
+        let mut field = TrackedField::new(ALARM_TOPIC, 0.0);
+        published(
+            &mut field,
+            text(alarm_payload(&["Sensor failing".to_string()])),
+            now,
+        );
+        assert!(should_publish(
+            &field,
+            text(alarm_payload(&["Sensor failinh".to_string()])),
+            now
+        ));
+
+        let start = Local::now();
+        let mut field = TrackedField::new(OPTIMAL_START_TOPIC, 0.0);
+        published(&mut field, text(optimal_start_payload(Some(start))), now);
+        let tomorrow = start + chrono::Duration::days(1);
+        assert!(should_publish(
+            &field,
+            text(optimal_start_payload(Some(tomorrow))),
+            now
+        ));
+    }
+
+    #[test]
+    fn republishes_after_heartbeat() {
+        let now = Instant::now();
+        let mut field = TrackedField::new(HUMIDITY_TOPIC, 1.0);
+        published(&mut field, numeric(40.0), now);
+
+        assert!(!should_publish(
+            &field,
+            numeric(40.0),
+            now + PUBLISH_HEARTBEAT / 2
+        ));
+        assert!(should_publish(
+            &field,
+            numeric(40.0),
+            now + PUBLISH_HEARTBEAT
+        ));
+        assert_eq!(
+            field.heartbeat_due(now + PUBLISH_HEARTBEAT / 2),
+            Some(PUBLISH_HEARTBEAT / 2)
+        );
+    }
+}
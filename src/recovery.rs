@@ -0,0 +1,315 @@
+use crate::VARIANCE;
+use chrono::Duration as ChronoDuration;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::prelude::*;
+use std::str::FromStr;
+use std::time::Instant;
+
+/// Recoveries kept for learning from, the oldest are dropped first.
+const MAX_SAMPLES: usize = 30;
+/// Smaller rises include ordinary cycling across the hysteresis band, which is mostly the
+/// radiators warming up, so aren't learned from.
+const MIN_RISE: f32 = 2.0 * VARIANCE;
+/// Heating rate assumed until one has been learned, in Fahrenheit per hour.
+const DEFAULT_RATE: f32 = 2.0;
+/// Slowest rate predicted, so one bad sample can't have the heating start hours early.
+const MIN_RATE: f32 = 0.5;
+/// Samples with the outdoor temperature needed before the rate is fitted against it.
+const MIN_FIT_SAMPLES: usize = 4;
+/// And how spread out their indoor to outdoor differences must be, as a variance.
+const MIN_FIT_SPREAD: f32 = 4.0;
+/// Never start heating earlier than this before a scheduled change.
+const MAX_LEAD_MINUTES: i64 = 3 * 60;
+
+/// One recovery, from heating starting to reaching the target.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Sample {
+    /// Fahrenheit per hour
+    rate: f32,
+    /// Indoor less outdoor temperature when it started, if the outdoor temperature was known
+    delta: Option<f32>,
+}
+
+/// A recovery being timed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Recovery {
+    started: Instant,
+    from: f32,
+    target: f32,
+    delta: Option<f32>,
+}
+
+/// Learns how quickly the room warms up, to predict how long a scheduled rise will take.
+///
+/// Each time heating starts well below the target the rise is timed until the target's reached,
+/// radiator warm up included. Once there are enough samples with the outdoor temperature the rate
+/// is fitted against the indoor to outdoor difference, as the room warms slower the colder it is
+/// outside, otherwise the average is used.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct RecoveryModel {
+    samples: VecDeque<Sample>,
+    current: Option<Recovery>,
+}
+
+impl RecoveryModel {
+    /// Watch a reading, returning whether a recovery finished and was learned from.
+    ///
+    /// A recovery is dropped if the target changes before it's reached.
+    pub(crate) fn observe(
+        &mut self,
+        temperature: f32,
+        target: f32,
+        running: bool,
+        outdoor: Option<f32>,
+        now: Instant,
+    ) -> bool {
+        match self.current {
+            Some(recovery) if recovery.target != target => self.current = None,
+            Some(recovery) if temperature >= target => {
+                self.current = None;
+
+                let hours = now
+                    .saturating_duration_since(recovery.started)
+                    .as_secs_f32()
+                    / 3600.0;
+                if hours > 0.0 {
+                    self.samples.push_back(Sample {
+                        rate: (temperature - recovery.from) / hours,
+                        delta: recovery.delta,
+                    });
+                    if self.samples.len() > MAX_SAMPLES {
+                        self.samples.pop_front();
+                    }
+                    return true;
+                }
+            }
+            _ => {}
+        }
+
+        if self.current.is_none() && running && target - temperature >= MIN_RISE {
+            self.current = Some(Recovery {
+                started: now,
+                from: temperature,
+                target,
+                delta: outdoor.map(|outdoor| temperature - outdoor),
+            });
+        }
+
+        false
+    }
+
+    /// Stop timing, e.g. when the heating is switched off.
+    pub(crate) fn reset(&mut self) {
+        self.current = None;
+    }
+
+    /// Predicted heating rate in Fahrenheit per hour.
+    pub(crate) fn rate(&self, temperature: f32, outdoor: Option<f32>) -> f32 {
+        let rate = match (self.fit(), outdoor) {
+            (Some((base, slope)), Some(outdoor)) => base + slope * (temperature - outdoor),
+            _ if self.samples.is_empty() => DEFAULT_RATE,
+            _ => {
+                self.samples.iter().map(|sample| sample.rate).sum::<f32>()
+                    / self.samples.len() as f32
+            }
+        };
+
+        rate.max(MIN_RATE)
+    }
+
+    /// How long before a scheduled change heating needs to start to reach `target` on time.
+    pub(crate) fn lead(
+        &self,
+        temperature: f32,
+        target: f32,
+        outdoor: Option<f32>,
+    ) -> ChronoDuration {
+        if target <= temperature {
+            return ChronoDuration::zero();
+        }
+
+        let hours = (target - temperature) / self.rate(temperature, outdoor);
+        let minutes = (hours * 60.0).ceil() as i64;
+
+        ChronoDuration::minutes(minutes.min(MAX_LEAD_MINUTES))
+    }
+
+    /// Least squares line through the rates against the indoor to outdoor difference, as the
+    /// rate at no difference and the change per degree.
+    fn fit(&self) -> Option<(f32, f32)> {
+        let points: Vec<(f32, f32)> = self
+            .samples
+            .iter()
+            .filter_map(|sample| sample.delta.map(|delta| (delta, sample.rate)))
+            .collect();
+        if points.len() < MIN_FIT_SAMPLES {
+            return None;
+        }
+
+        let n = points.len() as f32;
+        let mean_delta = points.iter().map(|(delta, _)| delta).sum::<f32>() / n;
+        let mean_rate = points.iter().map(|(_, rate)| rate).sum::<f32>() / n;
+        let spread = points
+            .iter()
+            .map(|(delta, _)| (delta - mean_delta).powi(2))
+            .sum::<f32>()
+            / n;
+        if spread < MIN_FIT_SPREAD {
+            return None;
+        }
+
+        let covariance = points
+            .iter()
+            .map(|(delta, rate)| (delta - mean_delta) * (rate - mean_rate))
+            .sum::<f32>()
+            / n;
+        // Colder outside never makes the room warm up faster, whatever the noise says
+        let slope = (covariance / spread).min(0.0);
+
+        Some((mean_rate - slope * mean_delta, slope))
+    }
+
+    pub(crate) fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut file = File::open(path)?;
+        let mut contents = String::new();
+
+        file.read_to_string(&mut contents)?;
+
+        Ok(contents.parse()?)
+    }
+
+    pub(crate) fn save(&self, path: &str) -> Result<(), std::io::Error> {
+        let mut file = File::create(path)?;
+        file.write_all(self.to_string().as_bytes())
+    }
+}
+
+/// One `rate delta` sample per line, oldest first, with `-` for an unknown delta.
+impl FromStr for RecoveryModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut model = RecoveryModel::default();
+
+        for line in s.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let mut parts = line.split_whitespace();
+            let invalid = || format!("invalid recovery line {:?}", line);
+
+            let rate: f32 = parts
+                .next()
+                .and_then(|rate| rate.parse().ok())
+                .filter(|rate| *rate > 0.0)
+                .ok_or_else(invalid)?;
+            let delta = match parts.next() {
+                Some("-") => None,
+                Some(delta) => Some(delta.parse().map_err(|_| invalid())?),
+                None => return Err(invalid()),
+            };
+
+            model.samples.push_back(Sample { rate, delta });
+        }
+
+        while model.samples.len() > MAX_SAMPLES {
+            model.samples.pop_front();
+        }
+
+        Ok(model)
+    }
+}
+
+impl fmt::Display for RecoveryModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for sample in &self.samples {
+            match sample.delta {
+                Some(delta) => writeln!(f, "{} {}", sample.rate, delta)?,
+                None => writeln!(f, "{} -", sample.rate)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::room::Room;
+    use std::time::Duration;
+
+    /// Heat `room` flat out from where it is to `target`, watching every step.
+    fn recover(model: &mut RecoveryModel, room: &mut Room, target: f32, now: &mut Instant) {
+        while !model.observe(room.reading(), target, true, Some(room.outside), *now) {
+            room.advance(true);
+            *now += room.step();
+        }
+    }
+
+    #[test]
+    fn learns_rate_against_outdoor_temperature() {
+        let mut model = RecoveryModel::default();
+        let mut now = Instant::now();
+
+        for &outside in &[50.0, 40.0, 30.0, 20.0] {
+            let mut room = Room::bedroom();
+            room.outside = outside;
+            recover(&mut model, &mut room, 70.0, &mut now);
+        }
+
+        // Slower the colder it is, and predicting a colder day takes longer
+        let mild = model.rate(64.0, Some(45.0));
+        let cold = model.rate(64.0, Some(25.0));
+        assert!(cold < mild, "{} vs {}", cold, mild);
+        assert!(model.lead(64.0, 70.0, Some(25.0)) > model.lead(64.0, 70.0, Some(45.0)));
+
+        // Close to what it takes the simulated room
+        let mut room = Room::bedroom();
+        room.outside = 35.0;
+        let expected = model.lead(room.reading(), 70.0, Some(35.0));
+        let started = now;
+        recover(&mut model, &mut room, 70.0, &mut now);
+        let took = ChronoDuration::from_std(now - started).unwrap();
+        assert!(
+            (took - expected).num_minutes().abs() <= 15,
+            "took {} expected {}",
+            took,
+            expected
+        );
+    }
+
+    #[test]
+    fn averages_without_outdoor_temperature() {
+        let mut model = RecoveryModel::default();
+        let start = Instant::now();
+
+        assert_eq!(model.rate(64.0, None), DEFAULT_RATE);
+
+        // Cutting in just below the hysteresis band isn't a recovery
+        assert!(!model.observe(68.5, 70.0, true, None, start));
+        assert!(!model.observe(70.0, 70.0, true, None, start + Duration::from_secs(600)));
+
+        assert!(!model.observe(66.0, 70.0, true, None, start));
+        assert!(!model.observe(68.0, 70.0, true, None, start + Duration::from_secs(3600)));
+        assert!(model.observe(70.0, 70.0, true, None, start + Duration::from_secs(7200)));
+        assert_eq!(model.rate(64.0, Some(30.0)), 2.0);
+
+        // A new target part way through isn't timed
+        model.observe(66.0, 70.0, true, None, start);
+        model.observe(67.0, 67.5, true, None, start + Duration::from_secs(600));
+        assert!(!model.observe(70.0, 67.5, true, None, start + Duration::from_secs(1200)));
+
+        assert_eq!(model.lead(66.0, 70.0, None), ChronoDuration::hours(2));
+        assert_eq!(model.lead(50.0, 70.0, None), ChronoDuration::hours(3));
+        assert_eq!(model.lead(71.0, 70.0, None), ChronoDuration::zero());
+    }
+
+    #[test]
+    fn round_trips_through_text() {
+        let model: RecoveryModel = "2.5 30\n1.75 -\n".parse().unwrap();
+
+        assert_eq!(model.to_string(), "2.5 30\n1.75 -\n");
+        assert!("fast 30\n".parse::<RecoveryModel>().is_err());
+        assert!("-1 30\n".parse::<RecoveryModel>().is_err());
+    }
+}
@@ -0,0 +1,198 @@
+use chrono::{DateTime, Duration, Local, NaiveDateTime, NaiveTime};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+use std::io::prelude::*;
+use std::str::FromStr;
+
+/// A daily schedule of target temperatures.
+///
+/// Each entry sets the target from its time of day until the next entry, wrapping around midnight.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub(crate) struct Schedule {
+    entries: Vec<ScheduleEntry>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub(crate) struct ScheduleEntry {
+    pub(crate) time: NaiveTime,
+    pub(crate) target_temperature: f32,
+}
+
+/// Temporarily ignore the schedule and keep the current target.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Hold {
+    Indefinite,
+    Until(DateTime<Local>),
+}
+
+impl Hold {
+    pub(crate) fn expired(&self, now: DateTime<Local>) -> bool {
+        match self {
+            Hold::Indefinite => false,
+            Hold::Until(until) => now >= *until,
+        }
+    }
+}
+
+impl Schedule {
+    pub(crate) fn new(mut entries: Vec<ScheduleEntry>) -> Self {
+        entries.sort_by_key(|entry| entry.time);
+
+        Self { entries }
+    }
+
+    pub(crate) fn entries(&self) -> &[ScheduleEntry] {
+        &self.entries
+    }
+
+    /// The entry in effect at `now`, along with when it started.
+    pub(crate) fn current(&self, now: NaiveDateTime) -> Option<(NaiveDateTime, ScheduleEntry)> {
+        let today = now.date();
+
+        match self
+            .entries
+            .iter()
+            .rev()
+            .find(|entry| entry.time <= now.time())
+        {
+            Some(entry) => Some((today.and_time(entry.time), *entry)),
+            // Before the first entry of the day, so yesterday's last entry still applies
+            None => self
+                .entries
+                .last()
+                .map(|entry| ((today - Duration::days(1)).and_time(entry.time), *entry)),
+        }
+    }
+
+    /// The next entry to start after `now`, along with when it starts.
+    pub(crate) fn next(&self, now: NaiveDateTime) -> Option<(NaiveDateTime, ScheduleEntry)> {
+        let today = now.date();
+
+        match self.entries.iter().find(|entry| entry.time > now.time()) {
+            Some(entry) => Some((today.and_time(entry.time), *entry)),
+            // After the last entry of the day, so it's tomorrow's first
+            None => self
+                .entries
+                .first()
+                .map(|entry| ((today + Duration::days(1)).and_time(entry.time), *entry)),
+        }
+    }
+
+    pub(crate) fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut file = File::open(path)?;
+        let mut contents = String::new();
+
+        file.read_to_string(&mut contents)?;
+
+        Ok(contents.parse()?)
+    }
+
+    pub(crate) fn save(&self, path: &str) -> Result<(), std::io::Error> {
+        let mut file = File::create(path)?;
+        file.write_all(self.to_string().as_bytes())
+    }
+}
+
+/// One `HH:MM target` entry per line.
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut entries = Vec::new();
+
+        for line in s.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let mut parts = line.split_whitespace();
+            let time = parts
+                .next()
+                .and_then(|time| NaiveTime::parse_from_str(time, "%H:%M").ok())
+                .ok_or_else(|| format!("invalid time in schedule line {:?}", line))?;
+            let target_temperature = parts
+                .next()
+                .and_then(|target| target.parse().ok())
+                .ok_or_else(|| format!("invalid target in schedule line {:?}", line))?;
+
+            entries.push(ScheduleEntry {
+                time,
+                target_temperature,
+            });
+        }
+
+        Ok(Self::new(entries))
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for entry in &self.entries {
+            writeln!(
+                f,
+                "{} {}",
+                entry.time.format("%H:%M"),
+                entry.target_temperature
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd(2020, 1, 2).and_hms(hour, minute, 0)
+    }
+
+    #[test]
+    fn current_entry() {
+        let schedule: Schedule = "22:00 64\n07:00 70\n".parse().unwrap();
+
+        let (start, entry) = schedule.current(at(12, 0)).unwrap();
+        assert_eq!(start, at(7, 0));
+        assert_eq!(entry.target_temperature, 70.0);
+
+        let (start, entry) = schedule.current(at(22, 0)).unwrap();
+        assert_eq!(start, at(22, 0));
+        assert_eq!(entry.target_temperature, 64.0);
+    }
+
+    #[test]
+    fn wraps_around_midnight() {
+        let schedule: Schedule = "07:00 70\n22:00 64\n".parse().unwrap();
+
+        let (start, entry) = schedule.current(at(3, 0)).unwrap();
+        assert_eq!(start, NaiveDate::from_ymd(2020, 1, 1).and_hms(22, 0, 0));
+        assert_eq!(entry.target_temperature, 64.0);
+    }
+
+    #[test]
+    fn next_entry() {
+        let schedule: Schedule = "07:00 70\n22:00 64\n".parse().unwrap();
+
+        let (start, entry) = schedule.next(at(7, 0)).unwrap();
+        assert_eq!(start, at(22, 0));
+        assert_eq!(entry.target_temperature, 64.0);
+
+        let (start, _) = schedule.next(at(23, 0)).unwrap();
+        assert_eq!(start, NaiveDate::from_ymd(2020, 1, 3).and_hms(7, 0, 0));
+    }
+
+    #[test]
+    fn empty_schedule_has_no_entry() {
+        assert_eq!(Schedule::default().current(at(12, 0)), None);
+        assert_eq!(Schedule::default().next(at(12, 0)), None);
+    }
+
+    #[test]
+    fn round_trips_through_text() {
+        let schedule: Schedule = "07:00 70.5\n22:30 64\n".parse().unwrap();
+
+        assert_eq!(schedule.to_string(), "07:00 70.5\n22:30 64\n");
+        assert!("7am 70".parse::<Schedule>().is_err());
+    }
+}
@@ -0,0 +1,221 @@
+use chrono::NaiveTime;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+use std::io::prelude::*;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Units temperatures are shown in. Control and MQTT always use Fahrenheit.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Units {
+    Fahrenheit,
+    Celsius,
+}
+
+impl Units {
+    pub(crate) fn convert(self, fahrenheit: f32) -> f32 {
+        match self {
+            Units::Fahrenheit => fahrenheit,
+            Units::Celsius => (fahrenheit - 32.0) / 1.8,
+        }
+    }
+
+    pub(crate) fn symbol(self) -> char {
+        match self {
+            Units::Fahrenheit => 'F',
+            Units::Celsius => 'C',
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Units::Fahrenheit => "fahrenheit",
+            Units::Celsius => "celsius",
+        }
+    }
+}
+
+/// Time of day the backlight stays off unless a button is pressed, may wrap past midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub(crate) struct NightWindow {
+    pub(crate) start: NaiveTime,
+    pub(crate) end: NaiveTime,
+}
+
+impl NightWindow {
+    pub(crate) fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+/// `HH:MM-HH:MM`
+impl FromStr for NightWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut times = s
+            .splitn(2, '-')
+            .map(|time| NaiveTime::parse_from_str(time, "%H:%M"));
+
+        match (times.next(), times.next()) {
+            (Some(Ok(start)), Some(Ok(end))) => Ok(Self { start, end }),
+            _ => Err(format!("invalid night window {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for NightWindow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M")
+        )
+    }
+}
+
+/// Local preferences changed from the on-device menu.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Settings {
+    pub(crate) units: Units,
+    /// Seconds without a button press before the backlight turns off, `None` keeps it on
+    pub(crate) backlight_timeout_secs: Option<u64>,
+    /// Added to every sensor reading, in Fahrenheit
+    pub(crate) calibration_offset: f32,
+    pub(crate) night_window: Option<NightWindow>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            units: Units::Fahrenheit,
+            backlight_timeout_secs: Some(30),
+            calibration_offset: 0.0,
+            night_window: None,
+        }
+    }
+}
+
+impl Settings {
+    pub(crate) fn backlight_timeout(&self) -> Option<Duration> {
+        self.backlight_timeout_secs.map(Duration::from_secs)
+    }
+
+    pub(crate) fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut file = File::open(path)?;
+        let mut contents = String::new();
+
+        file.read_to_string(&mut contents)?;
+
+        Ok(contents.parse()?)
+    }
+
+    pub(crate) fn save(&self, path: &str) -> Result<(), std::io::Error> {
+        let mut file = File::create(path)?;
+        file.write_all(self.to_string().as_bytes())
+    }
+}
+
+/// One `key value` pair per line, missing keys keep their default.
+impl FromStr for Settings {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut settings = Settings::default();
+
+        for line in s.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let mut parts = line.split_whitespace();
+            let key = parts.next().unwrap_or_default();
+            let value = parts
+                .next()
+                .ok_or_else(|| format!("missing value in settings line {:?}", line))?;
+            let invalid = || format!("invalid value in settings line {:?}", line);
+
+            match key {
+                "units" => {
+                    settings.units = match value {
+                        "fahrenheit" => Units::Fahrenheit,
+                        "celsius" => Units::Celsius,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "backlight_timeout" => {
+                    settings.backlight_timeout_secs = match value {
+                        "never" => None,
+                        secs => Some(secs.parse().map_err(|_| invalid())?),
+                    }
+                }
+                "calibration_offset" => {
+                    settings.calibration_offset = value.parse().map_err(|_| invalid())?
+                }
+                "night_window" => {
+                    settings.night_window = match value {
+                        "off" => None,
+                        window => Some(window.parse()?),
+                    }
+                }
+                _ => return Err(format!("unknown setting {:?}", key)),
+            }
+        }
+
+        Ok(settings)
+    }
+}
+
+impl fmt::Display for Settings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "units {}", self.units.as_str())?;
+        match self.backlight_timeout_secs {
+            Some(secs) => writeln!(f, "backlight_timeout {}", secs)?,
+            None => writeln!(f, "backlight_timeout never")?,
+        }
+        writeln!(f, "calibration_offset {}", self.calibration_offset)?;
+        match self.night_window {
+            Some(window) => writeln!(f, "night_window {}", window),
+            None => writeln!(f, "night_window off"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_through_text() {
+        let settings = Settings {
+            units: Units::Celsius,
+            backlight_timeout_secs: None,
+            calibration_offset: -1.5,
+            night_window: Some("22:30-06:45".parse().unwrap()),
+        };
+
+        assert_eq!(settings.to_string().parse::<Settings>(), Ok(settings));
+        assert_eq!(
+            "units celsius\n"
+                .parse::<Settings>()
+                .unwrap()
+                .backlight_timeout_secs,
+            Some(30)
+        );
+        assert!("units kelvin\n".parse::<Settings>().is_err());
+        assert!("night_window 22:00\n".parse::<Settings>().is_err());
+    }
+
+    #[test]
+    fn night_window_wraps_midnight() {
+        let window: NightWindow = "22:00-07:00".parse().unwrap();
+
+        assert!(window.contains(NaiveTime::from_hms(23, 0, 0)));
+        assert!(window.contains(NaiveTime::from_hms(3, 0, 0)));
+        assert!(!window.contains(NaiveTime::from_hms(7, 0, 0)));
+        assert!(!window.contains(NaiveTime::from_hms(12, 0, 0)));
+    }
+}
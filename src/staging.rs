@@ -0,0 +1,329 @@
+use std::fmt;
+use std::fs::File;
+use std::io::prelude::*;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// A heating stage after the first, on its own relay.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Stage {
+    /// BCM number of the GPIO driving its relay
+    pub(crate) pin: u8,
+    /// Once on it stays on at least this long, and once off stays off at least this long
+    pub(crate) min_on_secs: u64,
+    pub(crate) min_off_secs: u64,
+    /// Auxiliary heat, e.g. resistance strips, which is locked out when it's mild outside
+    pub(crate) aux: bool,
+}
+
+/// Extra heating stages, from the staging file.
+///
+/// The first stage is the main relay, driven by the control algorithm. Each further stage comes
+/// on once the one before it has been running for `escalate_after_secs`, or straight away when
+/// the room is far enough below the target, and stays on until the call for heat ends.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct StagingConfig {
+    pub(crate) stages: Vec<Stage>,
+    /// Fahrenheit below the target for each further stage to come on without waiting
+    pub(crate) escalate_error: f32,
+    pub(crate) escalate_after_secs: u64,
+    /// Outdoor temperature above which auxiliary stages are locked out
+    pub(crate) aux_lockout: Option<f32>,
+}
+
+impl Default for StagingConfig {
+    fn default() -> Self {
+        Self {
+            stages: Vec::new(),
+            escalate_error: 2.0,
+            escalate_after_secs: 20 * 60,
+            aux_lockout: None,
+        }
+    }
+}
+
+impl StagingConfig {
+    pub(crate) fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut file = File::open(path)?;
+        let mut contents = String::new();
+
+        file.read_to_string(&mut contents)?;
+
+        Ok(contents.parse()?)
+    }
+
+    /// Whether auxiliary stages are locked out at this outdoor temperature.
+    ///
+    /// Without an outdoor temperature they're allowed, a cold room is worse than a big bill.
+    pub(crate) fn aux_locked_out(&self, outdoor: Option<f32>) -> bool {
+        matches!((self.aux_lockout, outdoor), (Some(lockout), Some(outdoor)) if outdoor > lockout)
+    }
+}
+
+/// One `key value` pair per line, missing keys keep their default. Each stage is a
+/// `stage pin min_on min_off` line, in order, with `aux` on the end for auxiliary heat.
+impl FromStr for StagingConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut config = StagingConfig::default();
+
+        for line in s.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let mut parts = line.split_whitespace();
+            let key = parts.next().unwrap_or_default();
+            let value = parts
+                .next()
+                .ok_or_else(|| format!("missing value in staging line {:?}", line))?;
+            let invalid = || format!("invalid value in staging line {:?}", line);
+
+            match key {
+                "stage" => {
+                    let mut next = || parts.next().and_then(|secs| secs.parse().ok());
+                    let (min_on_secs, min_off_secs) = match (next(), next()) {
+                        (Some(min_on), Some(min_off)) => (min_on, min_off),
+                        _ => return Err(invalid()),
+                    };
+                    let aux = match parts.next() {
+                        Some("aux") => true,
+                        None => false,
+                        Some(_) => return Err(invalid()),
+                    };
+
+                    config.stages.push(Stage {
+                        pin: value.parse().map_err(|_| invalid())?,
+                        min_on_secs,
+                        min_off_secs,
+                        aux,
+                    });
+                }
+                "escalate_error" => {
+                    config.escalate_error = value
+                        .parse()
+                        .ok()
+                        .filter(|error: &f32| *error > 0.0)
+                        .ok_or_else(invalid)?
+                }
+                "escalate_after" => {
+                    config.escalate_after_secs = value.parse().map_err(|_| invalid())?
+                }
+                "aux_lockout" => {
+                    config.aux_lockout = match value {
+                        "none" => None,
+                        value => Some(value.parse().map_err(|_| invalid())?),
+                    }
+                }
+                _ => return Err(format!("unknown staging setting {:?}", key)),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+impl fmt::Display for StagingConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for stage in &self.stages {
+            write!(
+                f,
+                "stage {} {} {}",
+                stage.pin, stage.min_on_secs, stage.min_off_secs
+            )?;
+            if stage.aux {
+                write!(f, " aux")?;
+            }
+            writeln!(f)?;
+        }
+        writeln!(f, "escalate_error {}", self.escalate_error)?;
+        writeln!(f, "escalate_after {}", self.escalate_after_secs)?;
+        match self.aux_lockout {
+            Some(lockout) => writeln!(f, "aux_lockout {}", lockout),
+            None => writeln!(f, "aux_lockout none"),
+        }
+    }
+}
+
+/// Relay state of one further stage.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct StageState {
+    on: bool,
+    /// When it last switched, `None` if it hasn't since starting
+    switched: Option<Instant>,
+}
+
+/// Decides which further stages run, given the first stage's call for heat.
+#[derive(Debug, Default)]
+pub(crate) struct Stager {
+    /// When the first stage last came on, while it's on
+    call_since: Option<Instant>,
+    states: Vec<StageState>,
+}
+
+impl Stager {
+    /// Work out the further stages' relays, returning whether any changed.
+    ///
+    /// `call` is whether the first stage is on, and `error` how far the room is below the target.
+    /// Without `escalate` no more stages come on, e.g. while the sensor is failing. Stages only go
+    /// off once they've been on their minimum time, and back on once off their minimum time.
+    pub(crate) fn update(
+        &mut self,
+        config: &StagingConfig,
+        call: bool,
+        error: f32,
+        escalate: bool,
+        outdoor: Option<f32>,
+        now: Instant,
+    ) -> bool {
+        self.states
+            .resize(config.stages.len(), StageState::default());
+        self.call_since = match self.call_since {
+            Some(since) if call => Some(since),
+            _ if call => Some(now),
+            _ => None,
+        };
+
+        let locked_out = config.aux_locked_out(outdoor);
+        let escalate_after = Duration::from_secs(config.escalate_after_secs);
+        // When the stage below came on, `None` once a stage is off so none above it come on
+        let mut below_since = self.call_since;
+        let mut level = 0;
+        let mut changed = false;
+
+        for (stage, state) in config.stages.iter().zip(self.states.iter_mut()) {
+            let available = !(stage.aux && locked_out);
+            if available {
+                level += 1;
+            }
+
+            let wanted = call
+                && available
+                && (state.on
+                    || escalate
+                        && below_since.is_some_and(|since| {
+                            error >= config.escalate_error * level as f32
+                                || now.saturating_duration_since(since) >= escalate_after
+                        }));
+
+            let min = if state.on {
+                stage.min_on_secs
+            } else {
+                stage.min_off_secs
+            };
+            let settled = state.switched.is_none_or(|switched| {
+                now.saturating_duration_since(switched) >= Duration::from_secs(min)
+            });
+            if wanted != state.on && settled {
+                state.on = wanted;
+                state.switched = Some(now);
+                changed = true;
+            }
+
+            if available {
+                below_since = match state.switched {
+                    Some(switched) if state.on => Some(switched),
+                    None if state.on => below_since,
+                    _ => None,
+                };
+            }
+        }
+
+        changed
+    }
+
+    /// Relay states of the further stages, in order.
+    pub(crate) fn relays(&self) -> Vec<bool> {
+        self.states.iter().map(|state| state.on).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> StagingConfig {
+        StagingConfig {
+            stages: vec![
+                Stage {
+                    pin: 17,
+                    min_on_secs: 300,
+                    min_off_secs: 180,
+                    aux: false,
+                },
+                Stage {
+                    pin: 27,
+                    min_on_secs: 60,
+                    min_off_secs: 60,
+                    aux: true,
+                },
+            ],
+            escalate_error: 2.0,
+            escalate_after_secs: 20 * 60,
+            aux_lockout: Some(35.0),
+        }
+    }
+
+    fn minutes(minutes: u64) -> Duration {
+        Duration::from_secs(minutes * 60)
+    }
+
+    #[test]
+    fn round_trips_through_text() {
+        let config = config();
+
+        assert_eq!(config.to_string().parse(), Ok(config));
+        assert_eq!("".parse(), Ok(StagingConfig::default()));
+        assert!("stage 17 300\n".parse::<StagingConfig>().is_err());
+        assert!("stage 17 300 180 strips\n"
+            .parse::<StagingConfig>()
+            .is_err());
+        assert!("escalate_error 0\n".parse::<StagingConfig>().is_err());
+    }
+
+    #[test]
+    fn escalates_by_error_and_time() {
+        let config = config();
+        let mut stager = Stager::default();
+        let start = Instant::now();
+
+        // Just below the target only the first stage runs, until it's taken too long
+        assert!(!stager.update(&config, true, 1.0, true, None, start));
+        assert!(!stager.update(&config, true, 1.0, true, None, start + minutes(19)));
+        assert!(stager.update(&config, true, 1.0, true, None, start + minutes(20)));
+        assert_eq!(stager.relays(), vec![true, false]);
+        assert!(stager.update(&config, true, 1.0, true, None, start + minutes(40)));
+        assert_eq!(stager.relays(), vec![true, true]);
+
+        // The call ending drops them once they've run long enough
+        let ended = start + minutes(40) + Duration::from_secs(30);
+        assert!(stager.update(&config, false, 0.0, true, None, ended));
+        assert_eq!(stager.relays(), vec![false, true]);
+        assert!(stager.update(&config, false, 0.0, true, None, start + minutes(45)));
+        assert_eq!(stager.relays(), vec![false, false]);
+
+        // Far below the target both come on at once, once each has been off long enough
+        let later = ended + minutes(5);
+        stager.update(&config, true, 4.5, true, None, later);
+        assert_eq!(stager.relays(), vec![true, false]);
+        stager.update(&config, true, 4.5, true, None, later + minutes(2));
+        assert_eq!(stager.relays(), vec![true, true]);
+
+        // No escalation while the sensor can't be trusted
+        let mut stager = Stager::default();
+        assert!(!stager.update(&config, true, 4.5, false, None, start));
+    }
+
+    #[test]
+    fn locks_out_aux_heat_when_mild() {
+        let config = config();
+        let mut stager = Stager::default();
+        let start = Instant::now();
+
+        stager.update(&config, true, 4.5, true, Some(40.0), start);
+        assert_eq!(stager.relays(), vec![true, false]);
+        stager.update(&config, true, 4.5, true, Some(30.0), start + minutes(1));
+        assert_eq!(stager.relays(), vec![true, true]);
+
+        // Warming up outside turns it off again, after its minimum run
+        stager.update(&config, true, 4.5, true, Some(36.0), start + minutes(2));
+        assert_eq!(stager.relays(), vec![true, false]);
+    }
+}
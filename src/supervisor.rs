@@ -0,0 +1,47 @@
+use crate::error::Result;
+use std::cmp;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tokio::time::delay_for;
+use tracing::{error, info, warn};
+
+const MIN_RESTART_DELAY: Duration = Duration::from_secs(1);
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(60);
+
+/// Run a task in the background, restarting it with exponential backoff if it returns an error
+/// or panics.
+///
+/// The task is recreated from `factory` on every restart, so anything it needs to keep across
+/// restarts (receivers, hardware handles) should be shared with the factory rather than moved
+/// into a single run.
+pub(crate) fn supervise<F, Fut>(name: &'static str, mut factory: F)
+where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut delay = MIN_RESTART_DELAY;
+
+        loop {
+            let started = Instant::now();
+
+            match tokio::spawn(factory()).await {
+                Ok(Ok(())) => {
+                    info!(task = name, "Task finished");
+                    break;
+                }
+                Ok(Err(e)) => error!(task = name, error = %e, "Task failed"),
+                Err(e) => error!(task = name, error = %e, "Task panicked"),
+            }
+
+            // A task that ran for a good while before failing gets a fresh backoff
+            if started.elapsed() > MAX_RESTART_DELAY {
+                delay = MIN_RESTART_DELAY;
+            }
+
+            warn!(task = name, ?delay, "Restarting task");
+            delay_for(delay).await;
+            delay = cmp::min(delay * 2, MAX_RESTART_DELAY);
+        }
+    });
+}